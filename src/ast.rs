@@ -1,16 +1,69 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use crate::types::Type;
 
+/// Location of a node in the source code, as both a byte range and the line/column of its start.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    /// Span covering both self and a later span.
+    pub fn join(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        let (line, col) = span.start_pos().line_col();
+        Span {
+            start: span.start(),
+            end: span.end(),
+            line,
+            col,
+        }
+    }
+}
+
+// kept short, since every node in the --debug-ast output has one
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} ({}..{})",
+            self.line, self.col, self.start, self.end
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AstNode {
+    pub kind: AstKind,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
-pub enum AstNode {
+pub enum AstKind {
     Unit,
+    PrefixExpr {
+        verb: PrefixVerb,
+        expr: Box<AstNode>,
+    },
     InfixExpr {
         verb: InfixVerb,
         lhs: Box<AstNode>,
         rhs: Box<AstNode>,
     },
     Integer(i64),
+    Float(f64),
     Boolean(bool),
     Ident(String),
     // Type(String),
@@ -19,9 +72,14 @@ pub enum AstNode {
     /// Note that there's multiple empty strings:  QuoteString([]) and StringLiteral("")
     QuoteString(Vec<AstNode>),
     StringLiteral(String),
+    /// `{x:.2}` within a string or command, which shows a float with that many decimal places.
+    Format {
+        expr: Box<AstNode>,
+        precision: usize,
+    },
     Block(Vec<AstNode>),
     Binding {
-        ident: String,
+        pattern: Pattern,
         ty: Type,
         expr: Box<AstNode>,
     },
@@ -29,25 +87,216 @@ pub enum AstNode {
         ident: String,
         expr: Box<AstNode>,
     },
-    Command(Vec<CommandToken>),
+    /// A single command, pipelines are built from these with InfixVerb::Pipe.
+    Command {
+        tokens: Vec<CommandToken>,
+        redirects: Vec<Redirection>,
+    },
     IfThenElse {
         cond: Box<AstNode>,
         t_block: Box<AstNode>,
         f_block: Box<AstNode>,
     },
+    TryCatch {
+        block: Box<AstNode>,
+        ident: String,
+        catch: Box<AstNode>,
+    },
+    /// Postfix `?`, which makes a failed command an error
+    Propagate(Box<AstNode>),
+    While {
+        cond: Box<AstNode>,
+        block: Box<AstNode>,
+    },
+    /// Iterates over a list, the lines output by a command or a range of ints.
+    For {
+        pattern: Pattern,
+        ty: Type,
+        iter: Box<AstNode>,
+        block: Box<AstNode>,
+    },
+    Break,
+    Continue,
+    /// `return expr;`, where the expr is Unit if it's omitted
+    Return(Box<AstNode>),
+    Match {
+        expr: Box<AstNode>,
+        arms: Vec<MatchArm>,
+    },
+
+    ListValue(Vec<AstNode>),
+    TupleValue(Vec<AstNode>),
+    /// `t.0`
+    TupleIndex {
+        expr: Box<AstNode>,
+        index: usize,
+    },
+    /// `xs[i]`, where a negative index counts from the end
+    Index {
+        expr: Box<AstNode>,
+        index: Box<AstNode>,
+    },
+    /// `xs[start..end]`, which is parsed separately from indexing with a range
+    Slice {
+        expr: Box<AstNode>,
+        start: Box<AstNode>,
+        end: Box<AstNode>,
+    },
 
     // RecordType {}
     RecordValue(HashMap<String, AstNode>),
+    /// `{ ..base, name: expr }`
+    RecordUpdate {
+        base: Box<AstNode>,
+        fields: HashMap<String, AstNode>,
+    },
+    /// `r.name`
+    Field {
+        expr: Box<AstNode>,
+        name: String,
+    },
+    /// `[tag: expr]`, where the expr is Unit if it's omitted
+    VariantValue {
+        tag: String,
+        expr: Box<AstNode>,
+    },
+    /// Keys and values of a map literal, in order.
+    MapValue(Vec<(AstNode, AstNode)>),
     Call {
         name: String,
         args: Vec<AstNode>,
+        /// `name: expr`, which are given after the positional arguments
+        named: Vec<(String, AstNode)>,
     },
     Function {
         name: String,
-        args: Vec<(String, Type)>,
+        args: Vec<Param>,
         out: Type,
         block: Box<AstNode>,
     },
+    /// `|x: int| -> int { x + 1 }`, an anonymous function
+    Closure {
+        args: Vec<(Pattern, Type)>,
+        out: Type,
+        block: Box<AstNode>,
+    },
+    /// Call of a function value, as in `f(x)(y)`. Calls by name are always `Call`, even when
+    /// the name is a variable.
+    Apply {
+        expr: Box<AstNode>,
+        args: Vec<AstNode>,
+    },
+    /// `type Name = ty;`, or an `enum` (which declares a variant type)
+    TypeDecl {
+        name: String,
+        ty: Type,
+    },
+}
+
+/// An argument of a function defined with `fn`, as in `times: int = 3`.
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub pattern: Pattern,
+    pub ty: Type,
+    /// Used when a call leaves the argument out. It's evaluated after the arguments before it
+    /// are bound, so it can use them.
+    pub default: Option<AstNode>,
+}
+
+/// One arm of a `match`, which is taken if its pattern matches and its guard (if any) is true.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<AstNode>,
+    pub body: AstNode,
+    pub span: Span,
+}
+
+/// Names bound by `let`, `for`, function arguments and the arms of a `match`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Ident(String),
+    /// `_`, which ignores the value
+    Wildcard,
+    /// Binds each item of a tuple, which must have as many items as the pattern.
+    Tuple(Vec<Pattern>),
+    /// `{name: p}`, which only looks at the listed fields.
+    Record(Vec<(String, Pattern)>),
+    /// `[tag: p]`, which only matches a variant with the tag.
+    Variant {
+        tag: String,
+        pattern: Box<Pattern>,
+    },
+    // literals, which only match an equal value
+    Int(i64),
+    Str(String),
+    Bool(bool),
+}
+
+impl Pattern {
+    /// Every name bound by the pattern.
+    pub fn idents(&self) -> Vec<&str> {
+        match self {
+            Pattern::Ident(ident) => vec![ident],
+            Pattern::Wildcard | Pattern::Int(_) | Pattern::Str(_) | Pattern::Bool(_) => Vec::new(),
+            Pattern::Tuple(ps) => ps.iter().flat_map(Pattern::idents).collect(),
+            Pattern::Record(fields) => fields.iter().flat_map(|(_, p)| p.idents()).collect(),
+            Pattern::Variant { pattern, .. } => pattern.idents(),
+        }
+    }
+}
+
+/// Patterns are displayed as they're written.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Ident(ident) => write!(f, "{ident}"),
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Tuple(ps) => {
+                write!(f, "(")?;
+                for (i, p) in ps.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{p}")?;
+                }
+                if ps.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Pattern::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, p)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{name}: {p}")?;
+                }
+                write!(f, "}}")
+            }
+            Pattern::Variant { tag, pattern } => write!(f, "[{tag}: {pattern}]"),
+            Pattern::Int(x) => write!(f, "{x}"),
+            Pattern::Str(s) => write!(f, "\"{s}\""),
+            Pattern::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PrefixVerb {
+    Negate,
+    Not,
+}
+
+impl fmt::Display for PrefixVerb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = match self {
+            PrefixVerb::Negate => "-",
+            PrefixVerb::Not => "!",
+        };
+        write!(f, "{verb}")
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -56,9 +305,110 @@ pub enum InfixVerb {
     Minus,
     Times,
     Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    And,
+    Or,
+    /// Connects the stdout of one command to the stdin of another.
+    Pipe,
+    /// `a..b`, the ints from `a` up to but not including `b`
+    Range,
+}
+
+impl fmt::Display for InfixVerb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = match self {
+            InfixVerb::Plus => "+",
+            InfixVerb::Minus => "-",
+            InfixVerb::Times => "*",
+            InfixVerb::Divide => "/",
+            InfixVerb::Modulo => "%",
+            InfixVerb::Equal => "==",
+            InfixVerb::NotEqual => "!=",
+            InfixVerb::Less => "<",
+            InfixVerb::Greater => ">",
+            InfixVerb::LessEqual => "<=",
+            InfixVerb::GreaterEqual => ">=",
+            InfixVerb::And => "&&",
+            InfixVerb::Or => "||",
+            InfixVerb::Pipe => "|",
+            InfixVerb::Range => "..",
+        };
+        write!(f, "{verb}")
+    }
 }
 
 /// Within each CommandToken, all the parts get concatenated together
 /// each part is either actual text or an expression to evaluate into a Value::Str
 #[derive(Debug, Clone)]
-pub struct CommandToken(pub Vec<AstNode>);
+pub struct CommandToken {
+    pub parts: Vec<AstNode>,
+    pub span: Span,
+}
+
+/// Redirection of a command's stdin, stdout or stderr, such as `> out.txt`.
+#[derive(Debug, Clone)]
+pub struct Redirection {
+    pub op: RedirectOp,
+    /// The file to redirect to or from, or the value to use as stdin for `<<<`.
+    /// Only `2>&1` has no target.
+    pub target: Option<CommandToken>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `< file`
+    StdinFile,
+    /// `<<< value`, where the value (a `str` or `list<str>`) is written to stdin
+    StdinValue,
+    /// `> file`
+    Stdout,
+    /// `>> file`
+    StdoutAppend,
+    /// `2> file`
+    Stderr,
+    /// `2>> file`
+    StderrAppend,
+    /// `2>&1`
+    StderrToStdout,
+}
+
+impl RedirectOp {
+    const ALL: [RedirectOp; 7] = [
+        RedirectOp::StdinFile,
+        RedirectOp::StdinValue,
+        RedirectOp::Stdout,
+        RedirectOp::StdoutAppend,
+        RedirectOp::Stderr,
+        RedirectOp::StderrAppend,
+        RedirectOp::StderrToStdout,
+    ];
+
+    /// The operator written as `symbol`, such as `>>`.
+    pub fn from_symbol(symbol: &str) -> Option<RedirectOp> {
+        RedirectOp::ALL
+            .into_iter()
+            .find(|op| op.to_string() == symbol)
+    }
+}
+
+impl fmt::Display for RedirectOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            RedirectOp::StdinFile => "<",
+            RedirectOp::StdinValue => "<<<",
+            RedirectOp::Stdout => ">",
+            RedirectOp::StdoutAppend => ">>",
+            RedirectOp::Stderr => "2>",
+            RedirectOp::StderrAppend => "2>>",
+            RedirectOp::StderrToStdout => "2>&1",
+        };
+        write!(f, "{op}")
+    }
+}
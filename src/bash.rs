@@ -0,0 +1,1228 @@
+use std::collections::HashMap;
+
+use crate::{ast::*, diagnostic::Diagnostic, typeck::Checker, types::Type};
+
+type Result<T> = std::result::Result<T, Diagnostic>;
+
+/// `i64::MIN` in bash arithmetic, where the literal would be read as `-(i64::MAX + 1)`.
+const I64_MIN: &str = "(-9223372036854775807 - 1)";
+
+fn unsupported(what: &str, span: Span) -> Diagnostic {
+    Diagnostic::new(format!("{what} is not supported by the bash backend"), span)
+}
+
+/// Quote a string as a single bash word, leaving simple words (like most command arguments)
+/// unquoted so the output stays readable.
+pub fn quote(s: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !s.is_empty() && s.chars().all(is_plain) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// Transpile a type checked program to a bash script.
+///
+/// Values are lowered as follows:
+/// - `str`, `int` and `bool` are plain shell variables named `k_<name>` (so they can't clobber
+///   variables like `PATH` or `IFS`), with bools as `true`/`false`
+/// - `list<T>` and `command` are bash arrays, so their elements/arguments are never re-split
+/// - functions are shell functions named `kley_<name>`, which take their arguments positionally
+///   and return their value in `__ret`, leaving stdout free for the commands they run
+///
+/// Anything without a reasonable bash equivalent (such as records) is reported as an error.
+pub fn transpile(ast: &AstNode, source_name: &str) -> Result<String> {
+    let mut emitter = Emitter::default();
+    emitter.line("#!/usr/bin/env bash");
+    emitter.line(format!("# generated by kley from {source_name}"));
+    // failing commands are an error in kley too, see `errexit`
+    emitter.line("set -o errexit -o pipefail");
+    let header = emitter.lines.len();
+    emitter.stmt(ast)?;
+    if emitter.uses_to_int {
+        let to_int = TO_INT.lines().map(String::from);
+        emitter.lines.splice(header..header, to_int);
+    }
+    Ok(emitter.lines.join("\n") + "\n")
+}
+
+/// Converts `$1` to an int in `__ret` the way `Value::convert` does, stopping the script if it
+/// isn't one. Anything else would be evaluated as an expression (running any command
+/// substitutions in it) once it's used in bash arithmetic, which also wraps around instead of
+/// failing when a number is too large.
+const TO_INT: &str = r#"__to_int() {
+    if [[ $1 =~ ^[[:space:]]*([-+]?)0*([0-9]+)[[:space:]]*$ ]]; then
+        local sign=${BASH_REMATCH[1]} digits=${BASH_REMATCH[2]}
+        if (( ${#digits} < 19 )) || [[ ${#digits} == 19 && ( $digits < 9223372036854775808 || $sign$digits == -9223372036854775808 ) ]]; then
+            __ret=$(( ${sign}10#$digits ))
+            return
+        fi
+    fi
+    printf 'error: cannot convert str "%s" to `int`\n' "$1" >&2
+    exit 1
+}"#;
+
+#[derive(Default)]
+struct Emitter {
+    lines: Vec<String>,
+    indent: usize,
+    /// Types of the variables and functions in scope, kept in step with the emitted code.
+    types: Checker,
+    /// kley variable names to bash variable names, one map per block.
+    scopes: Vec<HashMap<String, String>>,
+    /// Number of times a bash variable name has been declared, to rename shadowed variables.
+    declared: HashMap<String, usize>,
+    in_function: bool,
+    temps: usize,
+    /// Variables holding the `set -e` state from before each enclosing block which calls
+    /// `errexit`, since it only lasts until the end of the block in kley.
+    errexit_saves: Vec<String>,
+    /// Length of `errexit_saves` at the start of the innermost loop (or function), so `break`,
+    /// `continue` and `return` can restore the state from before leaving it.
+    loop_saves: usize,
+    /// Whether `TO_INT` has to be defined at the start of the script.
+    uses_to_int: bool,
+}
+
+impl Emitter {
+    fn line(&mut self, line: impl AsRef<str>) {
+        let line = format!("{}{}", "    ".repeat(self.indent), line.as_ref());
+        self.lines.push(line);
+    }
+
+    /// Emit the lines from `f` one level of indentation deeper, with `:` if there were none
+    /// since bash doesn't allow empty blocks.
+    fn indented(&mut self, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let start = self.lines.len();
+        self.indent += 1;
+        let out = f(self);
+        if self.lines.len() == start {
+            self.line(":");
+        }
+        self.indent -= 1;
+        out
+    }
+
+    /// Run `f` within a new block scope.
+    fn scoped<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let types = self.types.clone();
+        self.scopes.push(HashMap::new());
+        let out = f(self);
+        self.scopes.pop();
+        self.types = types;
+        out
+    }
+
+    fn resolve(&self, ident: &str) -> String {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(ident))
+            .cloned()
+            .unwrap_or_else(|| ident.to_string())
+    }
+
+    /// Pick a bash name for a new kley variable. Bash has no block scoping, so variables which
+    /// shadow another are renamed.
+    fn fresh_name(&mut self, ident: &str) -> String {
+        let count = self.declared.entry(ident.to_string()).or_insert(0);
+        let name = match *count {
+            0 => format!("k_{ident}"),
+            n => format!("k_{ident}_{n}"),
+        };
+        *count += 1;
+        name
+    }
+
+    /// Bring a kley variable into scope as the given bash variable.
+    fn define(&mut self, ident: &str, name: String) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(ident.to_string(), name);
+        }
+    }
+
+    /// Assignment to a new variable, which needs to be `local` inside a function. The `local`
+    /// is separate, as it would hide the status of a command substitution in the word.
+    fn assign_new(&mut self, name: &str, word: &str) {
+        if self.in_function {
+            self.line(format!("local {name}"));
+        }
+        self.line(format!("{name}={word}"));
+    }
+
+    /// Run `f` within the scope of a block, declaring its types and functions first.
+    ///
+    /// If the block calls `errexit`, the `set -e` state is restored once it ends, as the
+    /// setting only lasts for the block in the interpreter.
+    fn block<T>(&mut self, es: &[AstNode], f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let sets_errexit = es
+            .iter()
+            .any(|e| matches!(&e.kind, AstKind::Call { name, .. } if name == "errexit"));
+        if !sets_errexit {
+            return self.scoped(|s| {
+                s.hoist(es)?;
+                f(s)
+            });
+        }
+        let saved = self.temp();
+        self.line(format!("{saved}=\"$-\""));
+        self.errexit_saves.push(saved.clone());
+        let out = self.scoped(|s| {
+            s.hoist(es)?;
+            f(s)
+        });
+        self.errexit_saves.pop();
+        self.restore_errexit(&saved);
+        out
+    }
+
+    /// Go back to the `set -e` state saved in `saved` (the value of `$-`).
+    fn restore_errexit(&mut self, saved: &str) {
+        self.line(format!(
+            "if [[ ${saved} == *e* ]]; then set -e; else set +e; fi"
+        ));
+    }
+
+    /// Restore the `set -e` state from before the innermost loop (or function) for a `break`,
+    /// `continue` or `return`, in case a block within it changed it.
+    fn restore_loop_errexit(&mut self) {
+        if let Some(saved) = self.errexit_saves.get(self.loop_saves).cloned() {
+            self.restore_errexit(&saved);
+        }
+    }
+
+    /// Emit the body of a loop with `f`.
+    fn loop_body(&mut self, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let loop_saves = std::mem::replace(&mut self.loop_saves, self.errexit_saves.len());
+        let out = f(self);
+        self.loop_saves = loop_saves;
+        out
+    }
+
+    /// Stop the script if `cond` (a bash command list) succeeds, with an error like the
+    /// interpreter's. The message is a `printf` format for `args`.
+    fn fail_if(&mut self, cond: &str, message: &str, args: &[String]) {
+        let mut printf = format!("printf {}", quote(&format!("error: {message}\\n")));
+        for arg in args {
+            printf.push(' ');
+            printf.push_str(arg);
+        }
+        self.line(format!("if {cond}; then {printf} >&2; exit 1; fi"));
+    }
+
+    /// A new variable for holding intermediate values.
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        let name = format!("__t{}", self.temps);
+        if self.in_function {
+            self.line(format!("local {name}"));
+        }
+        name
+    }
+
+    /// A word for the bash word `word` converted to an int, checked with `TO_INT` since bash
+    /// arithmetic would run any code in it.
+    fn checked_int(&mut self, word: &str) -> String {
+        self.uses_to_int = true;
+        self.line(format!("__to_int {word}"));
+        let temp = self.temp();
+        self.line(format!("{temp}=\"$__ret\""));
+        format!("\"${temp}\"")
+    }
+
+    fn type_of(&self, exp: &AstNode) -> Result<Type> {
+        self.types
+            .type_of(exp)
+            .ok_or_else(|| Diagnostic::new("could not infer the type of this expression", exp.span))
+    }
+
+    fn stmt(&mut self, e: &AstNode) -> Result<()> {
+        match &e.kind {
+            AstKind::Binding { pattern, ty, expr } => {
+                let ty = &self.types.resolved(ty);
+                match pattern {
+                    Pattern::Ident(ident) => {
+                        // the new name only comes into scope after the expression is evaluated
+                        let name = self.fresh_name(ident);
+                        self.store(&name, ty, expr, true)?;
+                        self.define(ident, name);
+                        self.types.bind(ident, ty);
+                    }
+                    Pattern::Wildcard => self.stmt(expr)?,
+                    _ => {
+                        let word = self.convert_word(expr, &Type::Str)?;
+                        self.destructure(pattern, ty, &word, e.span)?;
+                    }
+                }
+            }
+            AstKind::Assign { ident, expr } => {
+                let ty = self.types.var_type(ident).cloned();
+                let ty = ty.ok_or_else(|| Diagnostic::new("unknown variable", e.span))?;
+                let name = self.resolve(ident);
+                self.store(&name, &ty, expr, false)?;
+            }
+            // emitted along with the rest of the block
+            AstKind::Function { .. } => {}
+            AstKind::Block(es) => self.block(es, |s| {
+                for e in es {
+                    s.stmt(e)?;
+                }
+                Ok(())
+            })?,
+            AstKind::Unit => {}
+            AstKind::Command { .. }
+            | AstKind::InfixExpr {
+                verb: InfixVerb::Pipe,
+                ..
+            } => {
+                let pipeline = self.pipeline(e)?;
+                self.line(pipeline);
+            }
+            AstKind::IfThenElse {
+                cond,
+                t_block,
+                f_block,
+            } => {
+                let cond = self.cond(cond)?;
+                self.line(format!("if {cond}; then"));
+                self.indented(|s| s.stmt(t_block))?;
+                // an omitted else is a bare unit
+                if !matches!(f_block.kind, AstKind::Unit) {
+                    self.line("else");
+                    self.indented(|s| s.stmt(f_block))?;
+                }
+                self.line("fi");
+            }
+            AstKind::While { cond, block } => {
+                let start = self.lines.len();
+                self.indent += 1;
+                let cond = self.cond(cond);
+                self.indent -= 1;
+                let setup = self.lines.split_off(start);
+                let cond = cond?;
+                if setup.is_empty() {
+                    self.line(format!("while {cond}; do"));
+                } else {
+                    // the setup (such as a function call) has to be redone for every check
+                    self.line("while true; do");
+                    self.lines.extend(setup);
+                    self.line(format!("    if ! {{ {cond}; }}; then break; fi"));
+                }
+                self.loop_body(|s| s.indented(|s| s.stmt(block)))?;
+                self.line("done");
+            }
+            AstKind::For {
+                pattern,
+                ty,
+                iter,
+                block,
+            } => {
+                let ty = self.types.resolved(ty);
+                self.for_loop(pattern, &ty, iter, block)?
+            }
+            // declared along with the rest of the block
+            AstKind::TypeDecl { .. } => {}
+            AstKind::Break => {
+                self.restore_loop_errexit();
+                self.line("break");
+            }
+            AstKind::Continue => {
+                self.restore_loop_errexit();
+                self.line("continue");
+            }
+            // the output is passed back in `__ret`, as at the end of Emitter::function
+            AstKind::Return(expr) => {
+                let out = self
+                    .types
+                    .return_type()
+                    .cloned()
+                    .expect("checked by typeck");
+                if out == Type::Unit {
+                    self.stmt(expr)?;
+                } else {
+                    let word = self.convert_word(expr, &out)?;
+                    self.line(format!("__ret={word}"));
+                }
+                self.restore_loop_errexit();
+                self.line("return");
+            }
+            AstKind::Call { name, args, .. } if name == "errexit" => {
+                match self.cond(&args[0])?.as_str() {
+                    "true" => self.line("set -e"),
+                    "false" => self.line("set +e"),
+                    cond => self.line(format!("if {cond}; then set -e; else set +e; fi")),
+                }
+            }
+            AstKind::Call { name, .. } if name == "allow_fail" => {
+                let pipeline = self.pipeline(e)?;
+                self.line(pipeline);
+            }
+            AstKind::Call { name, args, named } => {
+                self.call(name, args, named, e.span)?;
+            }
+            AstKind::Ident(_) if self.type_of(e)? == Type::Command => {
+                let argv = self.argv(e)?;
+                self.line(argv);
+            }
+            // anything else only matters for the side effects of evaluating it
+            _ => {
+                self.word(e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit the assignment of `expr` (converted to `ty`) to a bash variable.
+    fn store(&mut self, name: &str, ty: &Type, expr: &AstNode, new: bool) -> Result<()> {
+        let local = if new && self.in_function {
+            "local "
+        } else {
+            ""
+        };
+        match ty {
+            Type::Str | Type::Int | Type::Bool => {
+                let word = self.convert_word(expr, ty)?;
+                match new {
+                    true => self.assign_new(name, &word),
+                    false => self.line(format!("{name}={word}")),
+                }
+            }
+            Type::Unit => self.stmt(expr)?,
+            Type::Command => {
+                if let AstKind::Command { redirects, .. } = &expr.kind {
+                    if let Some(redirect) = redirects.first() {
+                        let what = "storing a command with redirection in a variable";
+                        return Err(unsupported(what, redirect.span));
+                    }
+                }
+                let argv = self.argv(expr)?;
+                self.line(format!("{local}{name}=({argv})"));
+            }
+            // an empty list only has a type from where it's stored
+            Type::List(t) => match (&expr.kind, self.types.type_of(expr)) {
+                (AstKind::ListValue(es), _) => {
+                    let mut words = Vec::new();
+                    for e in es {
+                        words.push(self.convert_word(e, t)?);
+                    }
+                    self.line(format!("{local}{name}=({})", words.join(" ")));
+                }
+                (
+                    AstKind::Slice {
+                        expr: src,
+                        start,
+                        end,
+                    },
+                    _,
+                ) => {
+                    let AstKind::Ident(src) = &src.kind else {
+                        return Err(unsupported("slicing this list expression", src.span));
+                    };
+                    let src = self.resolve(src);
+                    let start = self.list_offset(&src, start)?;
+                    let end = self.list_offset(&src, end)?;
+                    self.fail_if(
+                        &format!("(( {start} < 0 || {end} < {start} || {end} > ${{#{src}[@]}} ))"),
+                        "slice is out of bounds for a list of length %s",
+                        &[format!("\"${{#{src}[@]}}\"")],
+                    );
+                    self.line(format!(
+                        "{local}{name}=(\"${{{src}[@]:{start}:{end}-{start}}}\")"
+                    ));
+                }
+                (AstKind::Ident(src), Some(Type::List(_))) => {
+                    let src = self.resolve(src);
+                    self.line(format!("{local}{name}=(\"${{{src}[@]}}\")"));
+                }
+                (_, Some(Type::Str | Type::Command)) => {
+                    // split on whitespace, the same as converting a str to a list
+                    let word = self.convert_word(expr, &Type::Str)?;
+                    if !local.is_empty() {
+                        self.line(format!("local -a {name}"));
+                    }
+                    self.line(format!("read -r -d '' -a {name} <<< {word} || true"));
+                    if **t == Type::Int {
+                        self.uses_to_int = true;
+                        let i = self.temp();
+                        self.line(format!("for {i} in \"${{!{name}[@]}}\"; do"));
+                        self.line(format!("    __to_int \"${{{name}[{i}]}}\""));
+                        self.line(format!("    {name}[{i}]=$__ret"));
+                        self.line("done");
+                    }
+                }
+                _ => return Err(unsupported("this list expression", expr.span)),
+            },
+            _ => return Err(unsupported(&format!("the type `{ty}`"), expr.span)),
+        }
+        Ok(())
+    }
+
+    /// Split a line into the fields of a tuple pattern with `read`, like converting `str` to a
+    /// tuple. Only tuples of `str` are supported, since `read` can't check the fields.
+    fn destructure(&mut self, pattern: &Pattern, ty: &Type, word: &str, span: Span) -> Result<()> {
+        let (Pattern::Tuple(ps), Type::Tuple(ts)) = (pattern, ty) else {
+            return Err(unsupported(&format!("the pattern `{pattern}`"), span));
+        };
+        if ps.len() != ts.len() || ts.iter().any(|t| *t != Type::Str) {
+            return Err(unsupported(&format!("destructuring `{ty}`"), span));
+        }
+        let mut names = Vec::new();
+        for p in ps {
+            match p {
+                Pattern::Ident(ident) => {
+                    let name = self.fresh_name(ident);
+                    if self.in_function {
+                        self.line(format!("local {name}"));
+                    }
+                    names.push(name);
+                }
+                Pattern::Wildcard => names.push(String::from("_")),
+                _ => {
+                    return Err(unsupported("nested patterns", span));
+                }
+            }
+        }
+        self.line(format!("read -r {} <<< {word}", names.join(" ")));
+        for (p, name) in ps.iter().zip(names) {
+            if let Pattern::Ident(ident) = p {
+                self.define(ident, name);
+            }
+        }
+        self.types.bind_pattern(pattern, ty, span);
+        Ok(())
+    }
+
+    fn for_loop(
+        &mut self,
+        pattern: &Pattern,
+        ty: &Type,
+        iter: &AstNode,
+        block: &AstNode,
+    ) -> Result<()> {
+        // a tuple pattern loops over the lines, then splits each one in the body
+        let name = match pattern {
+            Pattern::Ident(ident) => self.fresh_name(ident),
+            _ => self.fresh_name("__line"),
+        };
+        let iter_ty = self.type_of(iter)?;
+        let mut range_counter = None;
+        let header = match (&iter.kind, iter_ty.clone()) {
+            (
+                AstKind::InfixExpr {
+                    verb: InfixVerb::Range,
+                    lhs,
+                    rhs,
+                },
+                _,
+            ) => {
+                let start = self.arith(lhs)?;
+                // the end is only evaluated once in kley, but every iteration in bash
+                let end = match &rhs.kind {
+                    AstKind::Integer(x) => x.to_string(),
+                    _ => {
+                        let end = self.arith(rhs)?;
+                        let temp = self.temp();
+                        self.line(format!("{temp}=$(( {end} ))"));
+                        temp
+                    }
+                };
+                // counted separately, since assigning to the variable in the body doesn't
+                // change which ints are left in kley
+                let counter = self.temp();
+                range_counter = Some(counter.clone());
+                format!("for (( {counter} = {start}; {counter} < {end}; {counter}++ )); do")
+            }
+            (AstKind::Ident(var), Type::List(_)) => {
+                format!("for {name} in \"${{{}[@]}}\"; do", self.resolve(var))
+            }
+            (_, Type::Command) => {
+                // run to completion first, so a failure stops the script before the loop
+                let pipeline = self.pipeline(iter)?;
+                let output = self.temp();
+                self.line(format!("{output}=\"$({pipeline})\""));
+                let lines = self.temp();
+                self.line(format!(
+                    "readarray -t {lines} < <(printf '%s' \"${output}\")"
+                ));
+                format!("for {name} in \"${{{lines}[@]}}\"; do")
+            }
+            (_, ty @ Type::List(_)) => {
+                let items = self.temp();
+                self.store(&items, &ty, iter, false)?;
+                format!("for {name} in \"${{{items}[@]}}\"; do")
+            }
+            (_, from) => {
+                let what = format!("iterating over `{from}`");
+                return Err(unsupported(&what, iter.span));
+            }
+        };
+        if self.in_function {
+            self.line(format!("local {name}"));
+        }
+        self.line(header);
+        // each item is converted to the type of the pattern, as in the interpreter
+        let items_are_ints = match iter_ty {
+            Type::List(t) => *t == Type::Int,
+            _ => matches!(
+                iter.kind,
+                AstKind::InfixExpr {
+                    verb: InfixVerb::Range,
+                    ..
+                }
+            ),
+        };
+        self.scoped(|s| {
+            s.indented(|s| {
+                if let Some(counter) = range_counter {
+                    s.line(format!("{name}=${counter}"));
+                }
+                if *ty == Type::Int && !items_are_ints {
+                    let word = s.checked_int(&format!("\"${name}\""));
+                    s.line(format!("{name}={word}"));
+                }
+                match pattern {
+                    Pattern::Ident(ident) => {
+                        s.define(ident, name);
+                        s.types.bind(ident, ty);
+                    }
+                    Pattern::Wildcard => {}
+                    _ => s.destructure(pattern, ty, &format!("\"${name}\""), iter.span)?,
+                }
+                s.loop_body(|s| s.stmt(block))
+            })
+        })?;
+        self.line("done");
+        Ok(())
+    }
+
+    /// An offset into a bash array for a list index, counting back from the end if negative.
+    fn list_offset(&mut self, array: &str, e: &AstNode) -> Result<String> {
+        if let AstKind::Integer(x @ 0..) = e.kind {
+            return Ok(x.to_string());
+        }
+        let offset = self.arith(e)?;
+        let temp = self.temp();
+        self.line(format!("{temp}=$(( {offset} ))"));
+        self.line(format!(
+            "if (( {temp} < 0 )); then {temp}=$(( ${{#{array}[@]}} + {temp} )); fi"
+        ));
+        Ok(format!("${temp}"))
+    }
+
+    /// Declare the types and functions in a block, and define the functions before anything
+    /// else in it so they can be called from anywhere in the block.
+    fn hoist(&mut self, stmts: &[AstNode]) -> Result<()> {
+        self.types.hoist(stmts);
+        for e in stmts {
+            if let AstKind::Function {
+                name,
+                args,
+                out,
+                block,
+            } = &e.kind
+            {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|param| Param {
+                        ty: self.types.resolved(&param.ty),
+                        ..param.clone()
+                    })
+                    .collect();
+                let out = self.types.resolved(out);
+                self.function(name, &args, &out, block)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, name: &str, args: &[Param], out: &Type, block: &AstNode) -> Result<()> {
+        let types = self.types.clone();
+        let scopes = std::mem::replace(&mut self.scopes, vec![HashMap::new()]);
+        let in_function = std::mem::replace(&mut self.in_function, true);
+        let errexit_saves = std::mem::take(&mut self.errexit_saves);
+        let loop_saves = std::mem::replace(&mut self.loop_saves, 0);
+        self.types.enter_function(args, out, block.span);
+
+        self.line(format!("kley_{name}() {{"));
+        let result = self.indented(|s| {
+            for (i, param) in args.iter().enumerate() {
+                let ty = &param.ty;
+                if !matches!(ty, Type::Str | Type::Int | Type::Bool) {
+                    let what = format!("function arguments of type `{ty}`");
+                    return Err(unsupported(&what, block.span));
+                }
+                let arg = match &param.pattern {
+                    Pattern::Ident(arg) => arg,
+                    Pattern::Wildcard => continue,
+                    _ => {
+                        return Err(unsupported("patterns as arguments", block.span));
+                    }
+                };
+                let name = s.fresh_name(arg);
+                let given = format!("\"${}\"", i + 1);
+                // ints are checked on the way in, like any other conversion to an int
+                let bind_given = |s: &mut Self| {
+                    let word = match ty {
+                        Type::Int => s.checked_int(&given),
+                        _ => given.clone(),
+                    };
+                    s.assign_new(&name, &word);
+                };
+                match &param.default {
+                    None => bind_given(s),
+                    // arguments can only be left out at the end, see Emitter::call
+                    Some(default) => {
+                        s.line(format!("if (( $# > {i} )); then"));
+                        s.indented(|s| {
+                            bind_given(s);
+                            Ok(())
+                        })?;
+                        s.line("else");
+                        s.indented(|s| {
+                            let word = s.convert_word(default, ty)?;
+                            s.assign_new(&name, &word);
+                            Ok(())
+                        })?;
+                        s.line("fi");
+                    }
+                }
+                s.define(arg, name);
+            }
+            match out {
+                Type::Unit => s.stmt(block),
+                _ => {
+                    let word = s.convert_word(block, out)?;
+                    s.line(format!("__ret={word}"));
+                    Ok(())
+                }
+            }
+        });
+        self.line("}");
+
+        self.types = types;
+        self.scopes = scopes;
+        self.in_function = in_function;
+        self.errexit_saves = errexit_saves;
+        self.loop_saves = loop_saves;
+        result
+    }
+
+    /// Emit a call to a function, returning a word for its output (if it has one).
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[AstNode],
+        named: &[(String, AstNode)],
+        span: Span,
+    ) -> Result<Option<String>> {
+        match (name, args) {
+            _ if !named.is_empty() => {}
+            ("display", [e]) => return self.convert_word(e, &Type::Str).map(Some),
+            ("lookup" | "contains" | "keys" | "values" | "insert" | "remove", _) => {
+                return Err(unsupported(&format!("`{name}`"), span));
+            }
+            ("len", [e]) => {
+                let var = match (&e.kind, self.type_of(e)?) {
+                    (AstKind::Ident(var), Type::List(_)) => {
+                        return Ok(Some(format!("\"${{#{}[@]}}\"", self.resolve(var))));
+                    }
+                    (AstKind::Ident(var), Type::Str) => self.resolve(var),
+                    (_, Type::Str) => {
+                        let word = self.word(e)?;
+                        let temp = self.temp();
+                        self.line(format!("{temp}={word}"));
+                        temp
+                    }
+                    _ => return Err(unsupported("`len` of this expression", e.span)),
+                };
+                return Ok(Some(format!("\"${{#{var}}}\"")));
+            }
+            _ => {}
+        }
+        let Some((params, out)) = self.types.fn_signature(name) else {
+            return Err(Diagnostic::new(format!("unknown function `{name}`"), span));
+        };
+        let (params, out) = (params.to_vec(), out.clone());
+
+        // evaluated in the order they're written, then passed in the order of the parameters
+        let mut words = vec![None; params.len()];
+        for (slot, (arg, param)) in words.iter_mut().zip(args.iter().zip(params.iter())) {
+            *slot = Some(self.convert_word(arg, &param.ty)?);
+        }
+        for (arg_name, arg) in named {
+            let pattern = Pattern::Ident(arg_name.clone());
+            let i = params
+                .iter()
+                .position(|p| p.pattern == pattern)
+                .expect("checked by typeck");
+            words[i] = Some(self.convert_word(arg, &params[i].ty)?);
+        }
+        // bash only knows how many arguments were given, so only the last ones can be left out
+        let given = words.iter().rposition(Option::is_some).map_or(0, |i| i + 1);
+        if words[..given].iter().any(Option::is_none) {
+            let what = "leaving out an argument before one which is given";
+            return Err(unsupported(what, span));
+        }
+
+        let mut call = format!("kley_{name}");
+        for word in words.into_iter().flatten() {
+            call.push(' ');
+            call.push_str(&word);
+        }
+        self.line(call);
+
+        if out == Type::Unit {
+            return Ok(None);
+        }
+        // copied out right away, since the next call will overwrite it
+        let temp = self.temp();
+        self.line(format!("{temp}=\"$__ret\""));
+        Ok(Some(format!("\"${temp}\"")))
+    }
+
+    /// A bash word for a scalar (`str`, `int`, `bool` or `unit`) expression.
+    fn word(&mut self, e: &AstNode) -> Result<String> {
+        use InfixVerb::*;
+
+        let word = match &e.kind {
+            AstKind::Unit => String::from("''"),
+            AstKind::Integer(x) => x.to_string(),
+            AstKind::Boolean(b) => b.to_string(),
+            AstKind::StringLiteral(s) => quote(s),
+            AstKind::QuoteString(parts) => {
+                let mut word = String::new();
+                for part in parts {
+                    word.push_str(&self.convert_word(part, &Type::Str)?);
+                }
+                match word.is_empty() {
+                    true => String::from("''"),
+                    false => word,
+                }
+            }
+            AstKind::Ident(var) => format!("\"${}\"", self.resolve(var)),
+            AstKind::PrefixExpr {
+                verb: PrefixVerb::Negate,
+                ..
+            }
+            | AstKind::InfixExpr {
+                verb: Plus | Minus | Times | Divide | Modulo,
+                ..
+            } if self.type_of(e)? == Type::Int => format!("\"$(( {} ))\"", self.arith(e)?),
+            AstKind::InfixExpr {
+                verb: Plus,
+                lhs,
+                rhs,
+            } if self.type_of(e)? == Type::Str => {
+                let lhs = self.word(lhs)?;
+                let rhs = self.word(rhs)?;
+                format!("{lhs}{rhs}")
+            }
+            AstKind::PrefixExpr { .. } | AstKind::InfixExpr { .. } => {
+                if self.type_of(e)? != Type::Bool {
+                    return Err(unsupported("this operator", e.span));
+                }
+                let cond = self.cond(e)?;
+                let temp = self.temp();
+                self.line(format!(
+                    "if {cond}; then {temp}=true; else {temp}=false; fi"
+                ));
+                format!("\"${temp}\"")
+            }
+            AstKind::Block(_) | AstKind::IfThenElse { .. } => {
+                let ty = self.type_of(e)?;
+                self.convert_word(e, &ty)?
+            }
+            AstKind::Call { name, args, named } => match self.call(name, args, named, e.span)? {
+                Some(word) => word,
+                None => String::from("''"),
+            },
+            AstKind::Command { .. } => {
+                return Err(unsupported("using a command as a value", e.span))
+            }
+            AstKind::Index { expr, index } => match &expr.kind {
+                AstKind::Ident(var) => {
+                    // an unset element would just be empty in bash
+                    let var = self.resolve(var);
+                    let index = self.list_offset(&var, index)?;
+                    self.fail_if(
+                        &format!("(( {index} < 0 || {index} >= ${{#{var}[@]}} ))"),
+                        "index is out of bounds for a list of length %s",
+                        &[format!("\"${{#{var}[@]}}\"")],
+                    );
+                    format!("\"${{{var}[{index}]}}\"")
+                }
+                _ => return Err(unsupported("indexing this list expression", e.span)),
+            },
+            AstKind::ListValue(_) | AstKind::Slice { .. } => {
+                return Err(unsupported("this list expression", e.span))
+            }
+            AstKind::RecordValue(_) | AstKind::RecordUpdate { .. } | AstKind::Field { .. } => {
+                return Err(unsupported("records", e.span))
+            }
+            AstKind::MapValue(_) => return Err(unsupported("maps", e.span)),
+            AstKind::Closure { .. } | AstKind::Apply { .. } => {
+                return Err(unsupported("closures", e.span))
+            }
+            // bash arithmetic only has ints
+            AstKind::Float(_) | AstKind::Format { .. } => {
+                return Err(unsupported("floats", e.span))
+            }
+            AstKind::VariantValue { .. } => return Err(unsupported("variants", e.span)),
+            AstKind::Match { .. } => return Err(unsupported("`match`", e.span)),
+            AstKind::TupleValue(_) | AstKind::TupleIndex { .. } => {
+                return Err(unsupported("tuples", e.span))
+            }
+            AstKind::TryCatch { .. } => return Err(unsupported("`try`", e.span)),
+            AstKind::Propagate(_) => return Err(unsupported("`?`", e.span)),
+            AstKind::Binding { .. }
+            | AstKind::Assign { .. }
+            | AstKind::Function { .. }
+            | AstKind::While { .. }
+            | AstKind::For { .. }
+            | AstKind::Break
+            | AstKind::Continue
+            | AstKind::Return(_)
+            | AstKind::TypeDecl { .. } => {
+                unreachable!("statements are handled by Emitter::stmt")
+            }
+        };
+        Ok(word)
+    }
+
+    /// A bash word for an expression converted to a scalar type, following `Value::convert`.
+    fn convert_word(&mut self, e: &AstNode, ty: &Type) -> Result<String> {
+        match &e.kind {
+            // the conversion happens in each branch, as it does in the interpreter
+            AstKind::Block(es) => {
+                return self.block(es, |s| {
+                    let (last, stmts) = es.split_last().expect("blocks end with their value");
+                    for e in stmts {
+                        s.stmt(e)?;
+                    }
+                    s.convert_word(last, ty)
+                });
+            }
+            AstKind::IfThenElse {
+                cond,
+                t_block,
+                f_block,
+            } => {
+                let temp = self.temp();
+                let cond = self.cond(cond)?;
+                self.line(format!("if {cond}; then"));
+                self.indented(|s| {
+                    let word = s.convert_word(t_block, ty)?;
+                    s.line(format!("{temp}={word}"));
+                    Ok(())
+                })?;
+                self.line("else");
+                self.indented(|s| {
+                    let word = s.convert_word(f_block, ty)?;
+                    s.line(format!("{temp}={word}"));
+                    Ok(())
+                })?;
+                self.line("fi");
+                return Ok(format!("\"${temp}\""));
+            }
+            _ => {}
+        }
+
+        match (self.type_of(e)?, ty) {
+            // kept out of the word itself, where a failure could go unnoticed (such as in
+            // `local` or a here-string)
+            (Type::Command, Type::Str | Type::Int) => {
+                let pipeline = self.pipeline(e)?;
+                let temp = self.temp();
+                self.line(format!("{temp}=\"$({pipeline})\""));
+                if *ty == Type::Int {
+                    // only the first field is a number, as with `Value::convert`
+                    self.line(format!("read -r {temp} _ <<< \"${temp}\""));
+                    return Ok(self.checked_int(&format!("\"${temp}\"")));
+                }
+                // trimmed like `Value::convert` does, where `$( )` only drops trailing newlines
+                self.line(format!(
+                    "{temp}=\"${{{temp}#\"${{{temp}%%[![:space:]]*}}\"}}\""
+                ));
+                self.line(format!(
+                    "{temp}=\"${{{temp}%\"${{{temp}##*[![:space:]]}}\"}}\""
+                ));
+                Ok(format!("\"${temp}\""))
+            }
+            (Type::Str, Type::Int) => {
+                let word = self.word(e)?;
+                Ok(self.checked_int(&word))
+            }
+            (Type::List(_), Type::Str) => match &e.kind {
+                AstKind::Ident(var) => Ok(format!("\"${{{}[*]}}\"", self.resolve(var))),
+                _ => Err(unsupported("this list expression", e.span)),
+            },
+            (Type::Str | Type::Int | Type::Bool | Type::Unit, _) => self.word(e),
+            (from, _) => Err(unsupported(
+                &format!("converting `{from}` to `{ty}`"),
+                e.span,
+            )),
+        }
+    }
+
+    /// A bash arithmetic expression, for use within `$(( ))`. Runtime errors (such as overflow)
+    /// are checked for as the operands are evaluated, so the expression itself can't fail.
+    fn arith(&mut self, e: &AstNode) -> Result<String> {
+        use InfixVerb::*;
+
+        match &e.kind {
+            AstKind::Integer(x) => Ok(x.to_string()),
+            AstKind::Ident(var) => Ok(self.resolve(var)),
+            AstKind::PrefixExpr {
+                verb: PrefixVerb::Negate,
+                expr,
+            } => {
+                if let AstKind::Integer(x) = expr.kind {
+                    if let Some(x) = x.checked_neg() {
+                        return Ok(x.to_string());
+                    }
+                }
+                let x = self.arith(expr)?;
+                self.fail_if(
+                    &format!("(( {x} == {I64_MIN} ))"),
+                    "integer overflow evaluating `-(%s)`",
+                    &[format!("\"$(( {x} ))\"")],
+                );
+                // spaced out, since `--x` would decrement it
+                Ok(format!("(-({x}))"))
+            }
+            AstKind::InfixExpr { verb, lhs, rhs }
+                if matches!(verb, Plus | Minus | Times | Divide | Modulo) =>
+            {
+                let lhs = self.arith(lhs)?;
+                let rhs = self.arith(rhs)?;
+                // bash arithmetic wraps around, so overflow is checked for like in
+                // `eval_infix`
+                let overflow = format!(
+                    "integer overflow evaluating `%s {} %s`",
+                    verb.to_string().replace('%', "%%")
+                );
+                let values = [format!("\"$(( {lhs} ))\""), format!("\"$(( {rhs} ))\"")];
+                let temp = self.temp();
+                if matches!(verb, Divide | Modulo) {
+                    // the checks are left out for a literal divisor which can't fail them
+                    let divisor = rhs.parse::<i64>().ok();
+                    if !matches!(divisor, Some(x) if x != 0) {
+                        self.fail_if(&format!("(( {rhs} == 0 ))"), "division by zero", &[]);
+                    }
+                    if !matches!(divisor, Some(x) if x != -1) {
+                        let check = format!("(( {lhs} == {I64_MIN} && {rhs} == -1 ))");
+                        self.fail_if(&check, &overflow, &values);
+                    }
+                    self.line(format!("{temp}=$(( {lhs} {verb} {rhs} ))"));
+                    return Ok(temp);
+                }
+                self.line(format!("{temp}=$(( {lhs} {verb} {rhs} ))"));
+                let overflowed = match verb {
+                    Plus => {
+                        format!("({rhs} > 0 && {temp} < {lhs}) || ({rhs} < 0 && {temp} > {lhs})")
+                    }
+                    Minus => {
+                        format!("({rhs} > 0 && {temp} > {lhs}) || ({rhs} < 0 && {temp} < {lhs})")
+                    }
+                    _ => format!(
+                        "({lhs} == -1 && {rhs} == {I64_MIN}) \
+                         || ({lhs} != 0 && {lhs} != -1 && {temp} / {lhs} != {rhs})"
+                    ),
+                };
+                self.fail_if(&format!("(( {overflowed} ))"), &overflow, &values);
+                Ok(temp)
+            }
+            _ => self.word(e),
+        }
+    }
+
+    /// A bash command list which succeeds if the bool expression is true.
+    fn cond(&mut self, e: &AstNode) -> Result<String> {
+        use InfixVerb::*;
+
+        match &e.kind {
+            AstKind::Boolean(b) => Ok(b.to_string()),
+            AstKind::PrefixExpr {
+                verb: PrefixVerb::Not,
+                expr,
+            } => Ok(format!("! {{ {}; }}", self.cond(expr)?)),
+            AstKind::InfixExpr {
+                verb: verb @ (And | Or),
+                lhs,
+                rhs,
+            } => {
+                let lhs = self.cond(lhs)?;
+                let start = self.lines.len();
+                self.indent += 1;
+                let rhs = self.cond(rhs);
+                self.indent -= 1;
+                let rhs_lines = self.lines.split_off(start);
+                let rhs = rhs?;
+                if rhs_lines.is_empty() {
+                    return Ok(format!("{{ {lhs} {verb} {rhs}; }}"));
+                }
+
+                // the rhs needs setup (such as a function call), which only happens if the
+                // lhs doesn't short-circuit
+                let temp = self.temp();
+                let (init, lhs, rhs) = match verb {
+                    And => (false, lhs, rhs),
+                    _ => (true, format!("! {{ {lhs}; }}"), format!("! {{ {rhs}; }}")),
+                };
+                self.line(format!("{temp}={init}"));
+                self.line(format!("if {lhs}; then"));
+                self.lines.extend(rhs_lines);
+                self.line(format!("    if {rhs}; then {temp}={}; fi", !init));
+                self.line("fi");
+                Ok(format!("[ \"${temp}\" = true ]"))
+            }
+            AstKind::InfixExpr { verb, lhs, rhs }
+                if matches!(
+                    verb,
+                    Equal | NotEqual | Less | Greater | LessEqual | GreaterEqual
+                ) =>
+            {
+                match self.type_of(lhs)? {
+                    Type::Int => {
+                        let lhs = self.arith(lhs)?;
+                        let rhs = self.arith(rhs)?;
+                        Ok(format!("(( {lhs} {verb} {rhs} ))"))
+                    }
+                    Type::Str | Type::Bool => {
+                        let lhs = self.word(lhs)?;
+                        let rhs = self.word(rhs)?;
+                        Ok(match verb {
+                            Equal => format!("[ {lhs} = {rhs} ]"),
+                            NotEqual => format!("[ {lhs} != {rhs} ]"),
+                            Less => format!("[[ {lhs} < {rhs} ]]"),
+                            Greater => format!("[[ {lhs} > {rhs} ]]"),
+                            LessEqual => format!("! [[ {lhs} > {rhs} ]]"),
+                            GreaterEqual => format!("! [[ {lhs} < {rhs} ]]"),
+                            _ => unreachable!(),
+                        })
+                    }
+                    ty => Err(unsupported(&format!("comparing `{ty}` values"), e.span)),
+                }
+            }
+            _ => Ok(format!("[ {} = true ]", self.word(e)?)),
+        }
+    }
+
+    /// A command, or commands joined into a pipeline, to be run.
+    fn pipeline(&mut self, e: &AstNode) -> Result<String> {
+        match &e.kind {
+            AstKind::InfixExpr {
+                verb: InfixVerb::Pipe,
+                lhs,
+                rhs,
+            } => {
+                let lhs = self.pipeline(lhs)?;
+                let rhs = self.pipeline(rhs)?;
+                Ok(format!("{lhs} | {rhs}"))
+            }
+            AstKind::Call { name, args, .. } if name == "allow_fail" => {
+                Ok(format!("{{ {} || true; }}", self.pipeline(&args[0])?))
+            }
+            AstKind::Command { redirects, .. } => {
+                let mut words = vec![self.argv(e)?];
+                for redirect in redirects {
+                    words.push(self.redirect(redirect)?);
+                }
+                Ok(words.join(" "))
+            }
+            _ => self.argv(e),
+        }
+    }
+
+    fn redirect(&mut self, redirect: &Redirection) -> Result<String> {
+        let Some(target) = &redirect.target else {
+            return Ok(redirect.op.to_string());
+        };
+        let word = self.token(target)?;
+        match redirect.op {
+            // a here-string would add a newline, which kley doesn't
+            RedirectOp::StdinValue => match target.parts.as_slice() {
+                [part @ AstNode {
+                    kind: AstKind::Ident(var),
+                    ..
+                }] if matches!(self.type_of(part)?, Type::List(_)) => Ok(format!(
+                    "< <(printf '%s\\n' \"${{{}[@]}}\")",
+                    self.resolve(var)
+                )),
+                _ => Ok(format!("< <(printf '%s' {word})")),
+            },
+            op => Ok(format!("{op} {word}")),
+        }
+    }
+
+    /// A single bash word for a command token, concatenating its parts.
+    fn token(&mut self, token: &CommandToken) -> Result<String> {
+        let mut word = String::new();
+        for part in &token.parts {
+            match &part.kind {
+                AstKind::StringLiteral(s) => word.push_str(&quote(s)),
+                _ => word.push_str(&self.convert_word(part, &Type::Str)?),
+            }
+        }
+        Ok(word)
+    }
+
+    /// The words of a command, to be run or stored in an array.
+    fn argv(&mut self, e: &AstNode) -> Result<String> {
+        match &e.kind {
+            AstKind::Command { tokens, .. } => {
+                let mut words = Vec::new();
+                for token in tokens {
+                    words.push(self.token(token)?);
+                }
+                Ok(words.join(" "))
+            }
+            AstKind::Ident(var) => Ok(format!("\"${{{}[@]}}\"", self.resolve(var))),
+            // a bash array only holds the words of a single command
+            AstKind::InfixExpr {
+                verb: InfixVerb::Pipe,
+                ..
+            } => Err(unsupported("storing a pipeline in a variable", e.span)),
+            _ => Err(unsupported("this command expression", e.span)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_leaves_plain_words_alone() {
+        assert_eq!(quote("ls"), "ls");
+        assert_eq!(quote("./a-b_c.txt"), "./a-b_c.txt");
+        assert_eq!(quote("--name=x"), "--name=x");
+    }
+
+    #[test]
+    fn quote_special_characters() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("`id`"), "'`id`'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    /// Quoted words are given back unchanged by bash.
+    #[test]
+    fn quote_round_trips_through_bash() {
+        let words = [
+            "", "plain", "a b", "$HOME", "${x}", "`id`", "$(id)", "it's", "'", "\"", "\\", "*",
+            "a\nb", "!x", "#", "~", ";", "&",
+        ];
+        for word in words {
+            let out = std::process::Command::new("bash")
+                .arg("-c")
+                .arg(format!("printf %s {}", quote(word)))
+                .output()
+                .expect("bash is installed");
+            assert_eq!(String::from_utf8_lossy(&out.stdout), word);
+        }
+    }
+}
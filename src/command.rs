@@ -0,0 +1,447 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, PipeReader, PipeWriter, Read, Write},
+    process::{Child, Command, ExitStatus, Stdio},
+    rc::Rc,
+    thread::{self, JoinHandle},
+};
+
+use crate::{ast::RedirectOp, bash::quote};
+
+/// A single program and its arguments within a pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Applied in order, so `> out.txt 2>&1` sends both streams to the file, while
+    /// `2>&1 > out.txt` only sends stdout.
+    pub redirects: Vec<Redirect>,
+    /// Whether a non-zero exit status is expected, rather than an error.
+    pub allow_fail: bool,
+}
+
+/// A redirection with its target evaluated, which is empty for `2>&1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub op: RedirectOp,
+    pub target: String,
+}
+
+/// One or more commands, with the stdout of each stage connected to the stdin of the next.
+/// This is the value of a `command`, nothing is run until it's used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+    /// Set by `?`, so a failure is an error even if `errexit(false)` is in effect.
+    pub checked: bool,
+}
+
+/// Stage of a pipeline which exited with a non-zero status, when that isn't allowed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub program: String,
+    pub args: Vec<String>,
+    pub status: i64,
+}
+
+/// Failure to start one of the programs in a pipeline.
+#[derive(Debug)]
+pub struct SpawnError {
+    pub program: String,
+    pub error: io::Error,
+}
+
+/// The value of an `output`: the result of running a pipeline to completion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub stdout: Vec<u8>,
+    /// Empty unless stderr was captured.
+    pub stderr: Vec<u8>,
+    /// Exit status of each stage, in order.
+    pub statuses: Vec<i64>,
+    /// The stage which failed, see `Pipeline::failure`.
+    pub failure: Option<Failure>,
+}
+
+impl Output {
+    /// Exit status of the pipeline as a whole, which is that of its last stage.
+    pub fn status(&self) -> i64 {
+        *self
+            .statuses
+            .last()
+            .expect("pipelines have at least one stage")
+    }
+
+    pub fn success(&self) -> bool {
+        self.status() == 0
+    }
+
+    /// Stdout as a string, with any invalid UTF-8 replaced.
+    pub fn stdout_str(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    /// Stderr as a string, with any invalid UTF-8 replaced.
+    pub fn stderr_str(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+/// The value of a `process`: a pipeline which has been started but not waited for.
+///
+/// Clones refer to the same processes, which are waited for at most once.
+#[derive(Clone)]
+pub struct Process {
+    pipeline: Pipeline,
+    state: Rc<RefCell<ProcessState>>,
+}
+
+enum ProcessState {
+    Running {
+        children: Vec<Child>,
+        stdout: JoinHandle<Vec<u8>>,
+        stderr: JoinHandle<Vec<u8>>,
+    },
+    Done(Output),
+}
+
+impl Process {
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// Wait for every stage to finish, returning the output. Once finished, the same output is
+    /// returned by any later calls.
+    pub fn wait(&self) -> Output {
+        let mut state = self.state.borrow_mut();
+        if let ProcessState::Done(output) = &*state {
+            return output.clone();
+        }
+        let placeholder = ProcessState::Done(Output {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            statuses: Vec::new(),
+            failure: None,
+        });
+        let ProcessState::Running {
+            children,
+            stdout,
+            stderr,
+        } = std::mem::replace(&mut *state, placeholder)
+        else {
+            unreachable!()
+        };
+
+        let statuses = wait_all(children);
+        let output = Output {
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+            failure: self.pipeline.failure(&statuses),
+            statuses,
+        };
+        *state = ProcessState::Done(output.clone());
+        output
+    }
+}
+
+impl PartialEq for Process {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl fmt::Debug for Process {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self.state.borrow() {
+            ProcessState::Running { children, .. } => {
+                let pids: Vec<u32> = children.iter().map(Child::id).collect();
+                write!(f, "Process(running {pids:?})")
+            }
+            ProcessState::Done(output) => write!(f, "Process(done {:?})", output.statuses),
+        }
+    }
+}
+
+/// Exit status as a number, using the shell convention of 128 + the signal number for
+/// processes which were killed by a signal.
+pub fn status_code(status: ExitStatus) -> i64 {
+    if let Some(code) = status.code() {
+        return code.into();
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + i64::from(signal);
+        }
+    }
+    -1
+}
+
+/// Where a stage's stderr goes.
+enum Sink {
+    Default,
+    File(File),
+    /// Wherever stdout would go without redirection: the next stage, the captured output or
+    /// kley's own stdout.
+    Stdout,
+}
+
+fn open_output(path: &str, append: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))
+}
+
+fn read_all(mut reader: PipeReader) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+impl Stage {
+    /// Start this stage, reading from `stdin` (the previous stage) unless it's redirected.
+    /// If `pipe` is set, its stdout is returned so it can be read by the next stage or captured.
+    /// Unless redirected, stderr goes to `stderr` if given, otherwise it's inherited.
+    fn spawn(
+        &self,
+        stdin: Option<PipeReader>,
+        pipe: bool,
+        stderr: Option<&PipeWriter>,
+    ) -> io::Result<(Child, Option<PipeReader>)> {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+
+        let mut stdin = stdin.map(Stdio::from);
+        let mut stdin_value: Option<&str> = None;
+        let mut stdout: Option<File> = None;
+        let mut err_sink = Sink::Default;
+        for redirect in &self.redirects {
+            let path = redirect.target.as_str();
+            match redirect.op {
+                RedirectOp::StdinFile => {
+                    let file = File::open(path)
+                        .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))?;
+                    stdin = Some(file.into());
+                    stdin_value = None;
+                }
+                RedirectOp::StdinValue => {
+                    stdin = Some(Stdio::piped());
+                    stdin_value = Some(&redirect.target);
+                }
+                RedirectOp::Stdout => stdout = Some(open_output(path, false)?),
+                RedirectOp::StdoutAppend => stdout = Some(open_output(path, true)?),
+                RedirectOp::Stderr => err_sink = Sink::File(open_output(path, false)?),
+                RedirectOp::StderrAppend => err_sink = Sink::File(open_output(path, true)?),
+                RedirectOp::StderrToStdout => {
+                    err_sink = match &stdout {
+                        Some(file) => Sink::File(file.try_clone()?),
+                        None => Sink::Stdout,
+                    }
+                }
+            }
+        }
+
+        // the pipe is created even if stdout is redirected, so the next stage sees it closed
+        let (reader, writer) = match pipe {
+            true => {
+                let (reader, writer) = io::pipe()?;
+                (Some(reader), Some(writer))
+            }
+            false => (None, None),
+        };
+        let default_stdout = || -> io::Result<Stdio> {
+            match &writer {
+                Some(writer) => Ok(writer.try_clone()?.into()),
+                None => Ok(io::stdout().into()),
+            }
+        };
+
+        match stdout {
+            Some(file) => cmd.stdout(file),
+            None => cmd.stdout(default_stdout()?),
+        };
+        match (err_sink, stderr) {
+            (Sink::Default, None) => {}
+            (Sink::Default, Some(stderr)) => {
+                cmd.stderr(stderr.try_clone()?);
+            }
+            (Sink::File(file), _) => {
+                cmd.stderr(file);
+            }
+            (Sink::Stdout, _) => {
+                cmd.stderr(default_stdout()?);
+            }
+        }
+        if let Some(stdin) = stdin {
+            cmd.stdin(stdin);
+        }
+
+        let mut child = cmd.spawn()?;
+        if let (Some(value), Some(mut child_stdin)) = (stdin_value, child.stdin.take()) {
+            // written from another thread, so a large value can't block on a full pipe while
+            // the rest of the pipeline is started
+            let value = value.to_string();
+            thread::spawn(move || child_stdin.write_all(value.as_bytes()));
+        }
+        Ok((child, reader))
+    }
+}
+
+impl Pipeline {
+    /// The stage which failed given the exit status of each stage, if any did. Like bash's
+    /// `pipefail`, this is the last stage to exit with a non-zero status, except for stages
+    /// which allow failure and earlier stages killed by SIGPIPE (i.e. a later stage stopped
+    /// reading, as `head` does).
+    pub fn failure(&self, statuses: &[i64]) -> Option<Failure> {
+        const SIGPIPE_STATUS: i64 = 128 + 13;
+
+        let last = self.stages.len() - 1;
+        self.stages
+            .iter()
+            .zip(statuses)
+            .enumerate()
+            .rev()
+            .find(|(i, (stage, status))| {
+                let sigpipe = *i < last && **status == SIGPIPE_STATUS;
+                **status != 0 && !stage.allow_fail && !sigpipe
+            })
+            .map(|(_, (stage, status))| Failure {
+                program: stage.program.clone(),
+                args: stage.args.clone(),
+                status: *status,
+            })
+    }
+
+    /// The same pipeline, where every stage is allowed to fail.
+    pub fn allow_fail(mut self) -> Pipeline {
+        for stage in &mut self.stages {
+            stage.allow_fail = true;
+        }
+        self
+    }
+
+    /// Connect the end of this pipeline to the start of another.
+    pub fn pipe(mut self, other: Pipeline) -> Pipeline {
+        self.stages.extend(other.stages);
+        self.checked |= other.checked;
+        self
+    }
+
+    /// Start every stage of the pipeline, so they all run concurrently. The stdout of the last
+    /// stage is returned if `capture` is set, otherwise it's inherited.
+    fn spawn(
+        &self,
+        capture: bool,
+        stderr: Option<&PipeWriter>,
+    ) -> Result<(Vec<Child>, Option<PipeReader>), SpawnError> {
+        let mut children: Vec<Child> = Vec::new();
+        let mut stdout: Option<PipeReader> = None;
+        for (i, stage) in self.stages.iter().enumerate() {
+            let is_last = i + 1 == self.stages.len();
+            match stage.spawn(stdout.take(), !is_last || capture, stderr) {
+                Ok((child, next)) => {
+                    children.push(child);
+                    stdout = next;
+                }
+                Err(error) => {
+                    // earlier stages see their stdout closed, so they finish on their own
+                    for mut child in children {
+                        let _ = child.wait();
+                    }
+                    return Err(SpawnError {
+                        program: stage.program.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+        Ok((children, stdout))
+    }
+
+    /// Run the pipeline with stdout and stderr inherited, returning the stage which failed.
+    pub fn run(&self) -> Result<Option<Failure>, SpawnError> {
+        let (children, _) = self.spawn(false, None)?;
+        Ok(self.failure(&wait_all(children)))
+    }
+
+    /// Start the pipeline without waiting for it, capturing its stdout and stderr.
+    pub fn start(&self) -> Result<Process, SpawnError> {
+        let (err_reader, err_writer) = io::pipe().map_err(|error| SpawnError {
+            program: self.stages[0].program.clone(),
+            error,
+        })?;
+        let (children, stdout) = self.spawn(true, Some(&err_writer))?;
+        // only the children hold the write end now, so reading finishes once they exit
+        drop(err_writer);
+
+        let stdout = stdout.expect("stdout is captured");
+        let state = ProcessState::Running {
+            children,
+            stdout: read_all(stdout),
+            stderr: read_all(err_reader),
+        };
+        Ok(Process {
+            pipeline: self.clone(),
+            state: Rc::new(RefCell::new(state)),
+        })
+    }
+
+    /// Run the pipeline to completion, capturing the stdout of the last stage. Stderr is also
+    /// captured if `capture_stderr` is set, otherwise it's inherited.
+    pub fn output(&self, capture_stderr: bool) -> Result<Output, SpawnError> {
+        if capture_stderr {
+            return Ok(self.start()?.wait());
+        }
+        let (children, out) = self.spawn(true, None)?;
+        let mut stdout = Vec::new();
+        if let Some(mut out) = out {
+            let _ = out.read_to_end(&mut stdout);
+        }
+        let statuses = wait_all(children);
+        Ok(Output {
+            stdout,
+            stderr: Vec::new(),
+            failure: self.failure(&statuses),
+            statuses,
+        })
+    }
+}
+
+/// Pipelines are displayed like a shell command, quoted as they would be in bash.
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", quote(&stage.program))?;
+            for arg in &stage.args {
+                write!(f, " {}", quote(arg))?;
+            }
+            for redirect in &stage.redirects {
+                match redirect.op {
+                    RedirectOp::StderrToStdout => write!(f, " {}", redirect.op)?,
+                    RedirectOp::StdinValue => write!(f, " {} ..", redirect.op)?,
+                    op => write!(f, " {op} {}", quote(&redirect.target))?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn wait_all(children: Vec<Child>) -> Vec<i64> {
+    children
+        .into_iter()
+        .map(|mut child| child.wait().map_or(-1, status_code))
+        .collect()
+}
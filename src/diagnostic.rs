@@ -0,0 +1,73 @@
+use std::fmt;
+
+use crate::ast::Span;
+
+/// An error message attached to a location in the source code.
+///
+/// Rendered similarly to rustc (and pest's parse errors), with the offending line and a caret
+/// underline beneath the span.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn render(&self, source: &str, path: &str) -> String {
+        render(source, path, self.span, &self.message)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.col, self.message)
+    }
+}
+
+/// Render `message` with a snippet of `source` around `span`:
+///
+/// ```text
+/// error: cannot convert str "abc" to `int`
+///  --> script.ky:3:14
+///   |
+/// 3 | let n: int = "abc";
+///   |              ^^^^^
+/// ```
+///
+/// Spans covering multiple lines are underlined up to the end of their first line.
+pub fn render(source: &str, path: &str, span: Span, message: &str) -> String {
+    let mut out = format!("error: {message}\n");
+    if span.line == 0 {
+        // no location information, e.g. for an empty program
+        out.push_str(&format!(" --> {path}\n"));
+        return out;
+    }
+
+    let line_text = source.lines().nth(span.line - 1).unwrap_or("");
+    let line_no = span.line.to_string();
+    let pad = " ".repeat(line_no.len());
+
+    // the underline is measured in characters, not bytes
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = line_start + line_text.len();
+    let underline_end = span.end.clamp(span.start, line_end);
+    let offset = source[line_start..span.start].chars().count();
+    let width = source[span.start..underline_end].chars().count().max(1);
+
+    out.push_str(&format!("{pad}--> {path}:{}:{}\n", span.line, span.col));
+    out.push_str(&format!("{pad} |\n"));
+    out.push_str(&format!("{line_no} | {line_text}\n"));
+    out.push_str(&format!(
+        "{pad} | {}{}\n",
+        " ".repeat(offset),
+        "^".repeat(width)
+    ));
+    out
+}
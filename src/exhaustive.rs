@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use crate::{ast::Pattern, types::Type};
+
+/// What a pattern checks for at the top level of a value, such as the tag of a variant.
+///
+/// Tuples and records only have one constructor, whereas ints and strs have too many to list
+/// (so only a wildcard covers all of them).
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Single,
+    Tag(String),
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+/// A value of type `ty` which none of the patterns match, written as a pattern (such as
+/// `[err: _]`), or None if the patterns are exhaustive.
+///
+/// Patterns have to have been checked against `ty` first. This is the usefulness algorithm from
+/// "Warnings for pattern matching" (Maranget, 2007), which works on rows of patterns so that
+/// the items of a tuple are checked together rather than one at a time.
+pub fn missing(patterns: &[&Pattern], ty: &Type) -> Option<Pattern> {
+    let rows = patterns.iter().map(|p| vec![(*p).clone()]).collect();
+    missing_row(rows, std::slice::from_ref(ty)).map(|mut witness| witness.remove(0))
+}
+
+/// Patterns for the values of the types `tys` which aren't matched by any row.
+fn missing_row(rows: Vec<Vec<Pattern>>, tys: &[Type]) -> Option<Vec<Pattern>> {
+    let Some((ty, rest)) = tys.split_first() else {
+        // with nothing left to check, any row matches, so a value is missing only without one
+        return rows.is_empty().then(Vec::new);
+    };
+    let ty = ty.unfold();
+    let mut heads = Vec::new();
+    for row in &rows {
+        if let Some((ctor, _)) = head(&row[0], ty) {
+            if !heads.contains(&ctor) {
+                heads.push(ctor);
+            }
+        }
+    }
+    let all = all_ctors(ty);
+    match all {
+        // every constructor is used, so each one has to be covered by the rows which use it
+        Some(all) if all.iter().all(|c| heads.contains(c)) => all.into_iter().find_map(|ctor| {
+            let fields = fields(&ctor, ty);
+            let arity = fields.len();
+            let tys: Vec<_> = fields.into_iter().chain(rest.iter().cloned()).collect();
+            let mut witness = missing_row(specialize(&rows, &ctor, ty, arity), &tys)?;
+            let rest = witness.split_off(arity);
+            let mut out = vec![rebuild(&ctor, witness, ty)];
+            out.extend(rest);
+            Some(out)
+        }),
+        // otherwise any constructor which isn't used is missing, unless a wildcard covers it
+        all => {
+            let defaults = rows
+                .into_iter()
+                .filter(|row| head(&row[0], ty).is_none())
+                .map(|row| row[1..].to_vec())
+                .collect();
+            let witness = missing_row(defaults, rest)?;
+            let unused = all.and_then(|all| all.into_iter().find(|c| !heads.contains(c)));
+            let first = match unused {
+                Some(ctor) => {
+                    let wildcards = vec![Pattern::Wildcard; fields(&ctor, ty).len()];
+                    rebuild(&ctor, wildcards, ty)
+                }
+                None => Pattern::Wildcard,
+            };
+            Some(std::iter::once(first).chain(witness).collect())
+        }
+    }
+}
+
+/// The constructor a pattern checks for and the patterns for its fields, or None if the pattern
+/// matches anything.
+fn head(pattern: &Pattern, ty: &Type) -> Option<(Ctor, Vec<Pattern>)> {
+    match (pattern, ty) {
+        (Pattern::Ident(_) | Pattern::Wildcard, _) => None,
+        (Pattern::Tuple(ps), _) => Some((Ctor::Single, ps.clone())),
+        // fields which aren't listed are matched by a wildcard
+        (Pattern::Record(fields), Type::Record(ts)) => {
+            let ps = sorted_fields(ts)
+                .into_iter()
+                .map(|(name, _)| {
+                    let p = fields.iter().find(|(field, _)| field == name);
+                    p.map_or(Pattern::Wildcard, |(_, p)| p.clone())
+                })
+                .collect();
+            Some((Ctor::Single, ps))
+        }
+        (Pattern::Record(_), _) => unreachable!("checked by typeck"),
+        (Pattern::Variant { tag, pattern }, _) => {
+            Some((Ctor::Tag(tag.clone()), vec![(**pattern).clone()]))
+        }
+        (Pattern::Bool(b), _) => Some((Ctor::Bool(*b), Vec::new())),
+        (Pattern::Int(x), _) => Some((Ctor::Int(*x), Vec::new())),
+        (Pattern::Str(s), _) => Some((Ctor::Str(s.clone()), Vec::new())),
+    }
+}
+
+/// Every constructor of a type, or None if there are too many to list.
+fn all_ctors(ty: &Type) -> Option<Vec<Ctor>> {
+    match ty {
+        Type::Tuple(_) | Type::Record(_) => Some(vec![Ctor::Single]),
+        Type::Variant(tags) => {
+            let mut tags: Vec<_> = tags.keys().cloned().collect();
+            tags.sort();
+            Some(tags.into_iter().map(Ctor::Tag).collect())
+        }
+        Type::Bool => Some(vec![Ctor::Bool(true), Ctor::Bool(false)]),
+        _ => None,
+    }
+}
+
+/// Types of the fields of a constructor.
+fn fields(ctor: &Ctor, ty: &Type) -> Vec<Type> {
+    match (ctor, ty) {
+        (Ctor::Single, Type::Tuple(ts)) => ts.clone(),
+        (Ctor::Single, Type::Record(ts)) => sorted_fields(ts)
+            .into_iter()
+            .map(|(_, t)| t.clone())
+            .collect(),
+        (Ctor::Tag(tag), Type::Variant(tags)) => vec![tags[tag].clone()],
+        _ => Vec::new(),
+    }
+}
+
+fn sorted_fields(fields: &HashMap<String, Type>) -> Vec<(&String, &Type)> {
+    let mut fields: Vec<_> = fields.iter().collect();
+    fields.sort_by_key(|(name, _)| *name);
+    fields
+}
+
+/// Rows for the values with the constructor `ctor`, with the first pattern replaced by the
+/// patterns for its fields. Rows whose first pattern needs a different constructor are dropped.
+fn specialize(rows: &[Vec<Pattern>], ctor: &Ctor, ty: &Type, arity: usize) -> Vec<Vec<Pattern>> {
+    rows.iter()
+        .filter_map(|row| {
+            let fields = match head(&row[0], ty) {
+                None => vec![Pattern::Wildcard; arity],
+                Some((c, fields)) if c == *ctor => fields,
+                Some(_) => return None,
+            };
+            Some(fields.into_iter().chain(row[1..].iter().cloned()).collect())
+        })
+        .collect()
+}
+
+/// A pattern for a constructor with the given patterns for its fields.
+fn rebuild(ctor: &Ctor, fields: Vec<Pattern>, ty: &Type) -> Pattern {
+    match (ctor, ty) {
+        // only the fields which matter are shown, as in `{ok: false}`
+        (Ctor::Single, Type::Record(ts)) => {
+            let fields: Vec<_> = sorted_fields(ts)
+                .into_iter()
+                .zip(fields)
+                .filter(|(_, p)| *p != Pattern::Wildcard)
+                .map(|((name, _), p)| (name.clone(), p))
+                .collect();
+            match fields.is_empty() {
+                true => Pattern::Wildcard,
+                false => Pattern::Record(fields),
+            }
+        }
+        (Ctor::Single, _) => Pattern::Tuple(fields),
+        (Ctor::Tag(tag), _) => Pattern::Variant {
+            tag: tag.clone(),
+            pattern: Box::new(fields.into_iter().next().unwrap_or(Pattern::Wildcard)),
+        },
+        (Ctor::Bool(b), _) => Pattern::Bool(*b),
+        (Ctor::Int(x), _) => Pattern::Int(*x),
+        (Ctor::Str(s), _) => Pattern::Str(s.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ident(name: &str) -> Pattern {
+        Pattern::Ident(name.to_string())
+    }
+
+    fn variant(tag: &str, pattern: Pattern) -> Pattern {
+        Pattern::Variant {
+            tag: tag.to_string(),
+            pattern: Box::new(pattern),
+        }
+    }
+
+    /// `[ok: int, err: str]`
+    fn result_type() -> Type {
+        Type::Variant(HashMap::from([
+            (String::from("ok"), Type::Int),
+            (String::from("err"), Type::Str),
+        ]))
+    }
+
+    fn missing_str(patterns: &[Pattern], ty: &Type) -> Option<String> {
+        let patterns: Vec<_> = patterns.iter().collect();
+        missing(&patterns, ty).map(|p| p.to_string())
+    }
+
+    #[test]
+    fn wildcards_and_idents_cover_everything() {
+        assert_eq!(missing_str(&[Pattern::Wildcard], &Type::Int), None);
+        assert_eq!(missing_str(&[ident("x")], &result_type()), None);
+    }
+
+    #[test]
+    fn literals_need_a_wildcard() {
+        let patterns = [Pattern::Int(1), Pattern::Int(2)];
+        assert_eq!(missing_str(&patterns, &Type::Int).as_deref(), Some("_"));
+        let patterns = [Pattern::Bool(true)];
+        assert_eq!(
+            missing_str(&patterns, &Type::Bool).as_deref(),
+            Some("false")
+        );
+        let patterns = [Pattern::Bool(true), Pattern::Bool(false)];
+        assert_eq!(missing_str(&patterns, &Type::Bool), None);
+    }
+
+    #[test]
+    fn tuple_items_are_checked_together() {
+        let ty = Type::Tuple(vec![Type::Bool, Type::Bool]);
+        let tuple = |a, b| Pattern::Tuple(vec![Pattern::Bool(a), Pattern::Bool(b)]);
+        let patterns = [tuple(true, true), tuple(false, false)];
+        let missing = missing_str(&patterns, &ty).unwrap();
+        assert!(missing == "(true, false)" || missing == "(false, true)");
+
+        let patterns = [
+            tuple(true, true),
+            tuple(false, false),
+            Pattern::Tuple(vec![Pattern::Wildcard, Pattern::Bool(false)]),
+            Pattern::Tuple(vec![Pattern::Bool(false), Pattern::Wildcard]),
+        ];
+        assert_eq!(missing_str(&patterns, &ty), None);
+    }
+
+    #[test]
+    fn variants_need_every_tag() {
+        let ty = result_type();
+        let patterns = [variant("ok", ident("x"))];
+        assert_eq!(missing_str(&patterns, &ty).as_deref(), Some("[err: _]"));
+
+        let patterns = [
+            variant("ok", Pattern::Int(0)),
+            variant("err", Pattern::Wildcard),
+        ];
+        assert_eq!(missing_str(&patterns, &ty).as_deref(), Some("[ok: _]"));
+
+        let patterns = [variant("ok", ident("x")), variant("err", ident("e"))];
+        assert_eq!(missing_str(&patterns, &ty), None);
+    }
+
+    #[test]
+    fn records_only_check_the_listed_fields() {
+        let ty = Type::Record(HashMap::from([
+            (String::from("verbose"), Type::Bool),
+            (String::from("name"), Type::Str),
+        ]));
+        let record = |b| Pattern::Record(vec![(String::from("verbose"), Pattern::Bool(b))]);
+        let missing = missing_str(&[record(true)], &ty).unwrap();
+        assert!(missing.contains("verbose: false"), "{missing}");
+        assert_eq!(missing_str(&[record(true), record(false)], &ty), None);
+    }
+
+    #[test]
+    fn nested_patterns() {
+        let ty = Type::Tuple(vec![result_type(), Type::Bool]);
+        let patterns = [
+            Pattern::Tuple(vec![variant("ok", Pattern::Wildcard), Pattern::Wildcard]),
+            Pattern::Tuple(vec![variant("err", Pattern::Wildcard), Pattern::Bool(true)]),
+        ];
+        assert_eq!(
+            missing_str(&patterns, &ty).as_deref(),
+            Some("([err: _], false)")
+        );
+    }
+}
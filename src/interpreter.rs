@@ -1,334 +1,1632 @@
-use std::{collections::HashMap, process::Command};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt,
+    rc::Rc,
+};
 
-use crate::{ast::*, types::Type};
+use crate::{
+    ast::*,
+    bash::quote,
+    command::{Failure, Output, Pipeline, Process, Redirect, SpawnError, Stage},
+    diagnostic::Diagnostic,
+    typeck::Checker,
+    types::{Type, TypeEnv},
+};
 
+/// Errors which can occur while evaluating a kley program.
 #[derive(Debug, Clone)]
+pub enum RuntimeErrorKind {
+    UnknownIdentifier(String),
+    UnknownFunction(String),
+    NotAFunction(String),
+    /// Arguments which don't fit the function they're given to, such as too many of them.
+    InvalidArguments {
+        function: String,
+        message: String,
+    },
+    AssignToUnbound(String),
+    ConversionFailed {
+        value: Value,
+        ty: Type,
+    },
+    NonBoolCondition(Value),
+    InvalidOperand {
+        verb: PrefixVerb,
+        value: Value,
+    },
+    InvalidOperands {
+        verb: InfixVerb,
+        lhs: Value,
+        rhs: Value,
+    },
+    IntegerOverflow(String),
+    /// A float operation whose result is too large (or small) to represent.
+    FloatOverflow(String),
+    DivisionByZero,
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    SliceOutOfBounds {
+        start: i64,
+        end: i64,
+        len: usize,
+    },
+    KeyNotFound(Value),
+    /// A range with more ints than can be put in a list.
+    RangeTooLarge {
+        start: i64,
+        end: i64,
+    },
+    EmptyCommand,
+    CommandSpawn {
+        program: String,
+        message: String,
+    },
+    CommandFailed {
+        program: String,
+        args: Vec<String>,
+        status: i64,
+        /// Empty unless stderr was captured.
+        stderr: String,
+    },
+    /// Raised by `break` and caught by the enclosing loop.
+    Break,
+    /// Raised by `continue` and caught by the enclosing loop.
+    Continue,
+    /// Raised by `return` and caught by the enclosing function, which outputs the value.
+    Return(Value),
+}
+
+impl RuntimeErrorKind {
+    /// Name of the kind of error, as given to `catch` in the error's `kind` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeErrorKind::UnknownIdentifier(_) => "unknown_identifier",
+            RuntimeErrorKind::UnknownFunction(_) => "unknown_function",
+            RuntimeErrorKind::NotAFunction(_) => "not_a_function",
+            RuntimeErrorKind::InvalidArguments { .. } => "invalid_arguments",
+            RuntimeErrorKind::AssignToUnbound(_) => "assign_to_unbound",
+            RuntimeErrorKind::ConversionFailed { .. } => "conversion_failed",
+            RuntimeErrorKind::NonBoolCondition(_) => "non_bool_condition",
+            RuntimeErrorKind::InvalidOperand { .. } => "invalid_operand",
+            RuntimeErrorKind::InvalidOperands { .. } => "invalid_operands",
+            RuntimeErrorKind::IntegerOverflow(_) => "integer_overflow",
+            RuntimeErrorKind::FloatOverflow(_) => "float_overflow",
+            RuntimeErrorKind::DivisionByZero => "division_by_zero",
+            RuntimeErrorKind::IndexOutOfBounds { .. } => "index_out_of_bounds",
+            RuntimeErrorKind::SliceOutOfBounds { .. } => "slice_out_of_bounds",
+            RuntimeErrorKind::KeyNotFound(_) => "key_not_found",
+            RuntimeErrorKind::RangeTooLarge { .. } => "range_too_large",
+            RuntimeErrorKind::EmptyCommand => "empty_command",
+            RuntimeErrorKind::CommandSpawn { .. } => "command_spawn",
+            RuntimeErrorKind::CommandFailed { .. } => "command_failed",
+            RuntimeErrorKind::Break => "break",
+            RuntimeErrorKind::Continue => "continue",
+            RuntimeErrorKind::Return(_) => "return",
+        }
+    }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::UnknownIdentifier(ident) => {
+                write!(f, "unknown identifier `{ident}`")
+            }
+            RuntimeErrorKind::UnknownFunction(name) => write!(f, "unknown function `{name}`"),
+            RuntimeErrorKind::NotAFunction(name) => write!(f, "`{name}` is not a function"),
+            RuntimeErrorKind::InvalidArguments { function, message } => {
+                write!(f, "invalid arguments to `{function}`: {message}")
+            }
+            RuntimeErrorKind::AssignToUnbound(ident) => {
+                write!(
+                    f,
+                    "cannot assign to `{ident}`, it was never bound with `let`"
+                )
+            }
+            RuntimeErrorKind::ConversionFailed { value, ty } => {
+                write!(f, "cannot convert {} to `{ty}`", value.describe())
+            }
+            RuntimeErrorKind::NonBoolCondition(value) => {
+                write!(f, "condition must be a bool, found {}", value.describe())
+            }
+            RuntimeErrorKind::InvalidOperand { verb, value } => {
+                write!(f, "cannot apply `{verb}` to {}", value.describe())
+            }
+            RuntimeErrorKind::InvalidOperands { verb, lhs, rhs } => write!(
+                f,
+                "cannot apply `{verb}` to {} and {}",
+                lhs.describe(),
+                rhs.describe()
+            ),
+            RuntimeErrorKind::IntegerOverflow(expr) => {
+                write!(f, "integer overflow evaluating `{expr}`")
+            }
+            RuntimeErrorKind::FloatOverflow(expr) => {
+                write!(f, "float overflow evaluating `{expr}`")
+            }
+            RuntimeErrorKind::DivisionByZero => write!(f, "division by zero"),
+            RuntimeErrorKind::IndexOutOfBounds { index, len } => write!(
+                f,
+                "index `{index}` is out of bounds for a list of length {len}"
+            ),
+            RuntimeErrorKind::SliceOutOfBounds { start, end, len } => write!(
+                f,
+                "slice `{start}..{end}` is out of bounds for a list of length {len}"
+            ),
+            RuntimeErrorKind::KeyNotFound(key) => {
+                write!(f, "key {} not found in map", key.describe())
+            }
+            RuntimeErrorKind::RangeTooLarge { start, end } => {
+                write!(f, "range `{start}..{end}` is too large to make a list of")
+            }
+            RuntimeErrorKind::EmptyCommand => write!(f, "command has no program to run"),
+            RuntimeErrorKind::CommandSpawn { program, message } => {
+                write!(f, "failed to run `{program}`: {message}")
+            }
+            RuntimeErrorKind::CommandFailed {
+                program,
+                args,
+                status,
+                ..
+            } => {
+                let words: Vec<String> = std::iter::once(program)
+                    .chain(args)
+                    .map(|word| quote(word))
+                    .collect();
+                write!(
+                    f,
+                    "command `{}` failed with exit status {status}",
+                    words.join(" ")
+                )
+            }
+            RuntimeErrorKind::Break => write!(f, "`break` outside of a loop"),
+            RuntimeErrorKind::Continue => write!(f, "`continue` outside of a loop"),
+            RuntimeErrorKind::Return(_) => write!(f, "`return` outside of a function"),
+        }
+    }
+}
+
+/// A RuntimeErrorKind, along with the location of the expression which caused it.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: Box<RuntimeErrorKind>,
+    pub span: Span,
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, span: Span) -> Self {
+        RuntimeError {
+            kind: Box::new(kind),
+            span,
+        }
+    }
+
+    /// Whether this is a `break` or `continue` on its way to the enclosing loop (or a `return`
+    /// on its way to the enclosing function), rather than an actual error.
+    pub fn is_control_flow(&self) -> bool {
+        matches!(
+            *self.kind,
+            RuntimeErrorKind::Break | RuntimeErrorKind::Continue | RuntimeErrorKind::Return(_)
+        )
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.kind.to_string(), self.span)
+    }
+
+    /// The error as a value for `catch`, as described by `Type::error_record`.
+    pub fn to_value(&self) -> Value {
+        let (status, stderr) = match &*self.kind {
+            RuntimeErrorKind::CommandFailed { status, stderr, .. } => (*status, stderr.clone()),
+            RuntimeErrorKind::CommandSpawn { .. } => (127, String::new()),
+            _ => (1, String::new()),
+        };
+        Value::Record(HashMap::from_iter([
+            (
+                String::from("kind"),
+                Value::Str(self.kind.name().to_string()),
+            ),
+            (String::from("message"), Value::Str(self.kind.to_string())),
+            (String::from("status"), Value::Int(status)),
+            (String::from("stderr"), Value::Str(stderr)),
+        ]))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.diagnostic().fmt(f)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// The variables bound by `let` within one block, most recently bound first.
+///
+/// Frames are shared by every `Env` for the block and the blocks nested within it, so an
+/// assignment within a nested block updates the binding in the frame it belongs to.
+#[derive(Debug, Default)]
+struct Frame {
+    vars: RefCell<Vec<(String, Type, Value)>>,
+    parent: Option<Rc<Frame>>,
+}
+
+/// Cloning an `Env` gives another handle to the same scope, rather than a copy of it.
+#[derive(Debug, Clone, Default)]
 pub struct Env {
-    vars: Vec<(String, Type, Value)>,
+    frame: Rc<Frame>,
     fns: HashMap<String, AstNode>,
+    types: TypeEnv,
+    /// Set by `errexit(false);`, so commands which fail don't cause an error.
+    allow_fail: bool,
+}
+
+impl Env {
+    /// Variables in scope, most recently bound first.
+    pub fn vars(&self) -> Vec<(String, Type, Value)> {
+        self.frames()
+            .flat_map(|frame| frame.vars.borrow().clone())
+            .collect()
+    }
+
+    /// Functions in scope, as their AstKind::Function definitions.
+    pub fn fns(&self) -> impl Iterator<Item = &AstNode> {
+        self.fns.values()
+    }
+
+    /// Frames from innermost to outermost.
+    fn frames(&self) -> impl Iterator<Item = &Frame> {
+        std::iter::successors(Some(&*self.frame), |frame| frame.parent.as_deref())
+    }
+
+    /// Scope for a block nested within this one, whose bindings go away at the end of the block.
+    fn child(&self) -> Env {
+        Env {
+            frame: Rc::new(Frame {
+                vars: RefCell::default(),
+                parent: Some(self.frame.clone()),
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Bind a variable in the innermost frame, shadowing any other variable with its name.
+    fn bind(&self, ident: &str, ty: &Type, val: Value) {
+        let var = (ident.to_string(), ty.unfold().clone(), val);
+        self.frame.vars.borrow_mut().insert(0, var);
+    }
+
+    /// Bind each name in a pattern to the matching part of a value of type `ty`, failing if the
+    /// value doesn't match the pattern.
+    fn bind_pattern(
+        &self,
+        pattern: &Pattern,
+        ty: &Type,
+        val: Value,
+    ) -> Result<(), RuntimeErrorKind> {
+        match (pattern, ty.unfold(), val) {
+            (Pattern::Ident(ident), ty, val) => self.bind(ident, ty, val),
+            (Pattern::Wildcard, _, _) => {}
+            (Pattern::Int(x), _, Value::Int(y)) if *x == y => {}
+            (Pattern::Str(s), _, Value::Str(t)) if *s == t => {}
+            (Pattern::Bool(b), _, Value::Bool(c)) if *b == c => {}
+            (Pattern::Tuple(ps), Type::Tuple(ts), Value::Tuple(vs))
+                if ps.len() == ts.len() && ts.len() == vs.len() =>
+            {
+                for ((p, t), v) in ps.iter().zip(ts).zip(vs) {
+                    self.bind_pattern(p, t, v)?;
+                }
+            }
+            (Pattern::Record(fields), Type::Record(ts), Value::Record(mut vs))
+                if fields
+                    .iter()
+                    .all(|(name, _)| ts.contains_key(name) && vs.contains_key(name)) =>
+            {
+                for (name, p) in fields {
+                    self.bind_pattern(p, &ts[name], vs.remove(name).unwrap())?;
+                }
+            }
+            (Pattern::Variant { tag, pattern }, Type::Variant(ts), Value::Variant(t, v))
+                if *tag == t && ts.contains_key(tag) =>
+            {
+                self.bind_pattern(pattern, &ts[tag], *v)?;
+            }
+            (_, ty, value) => {
+                return Err(RuntimeErrorKind::ConversionFailed {
+                    value,
+                    ty: ty.clone(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Scope for the body of a function defined with `fn`, which only sees its arguments rather
+    /// than the caller's variables.
+    fn fn_env(&self) -> Env {
+        Env {
+            frame: Rc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Scope for a closure, with a copy of the most recent binding of each variable in scope.
+    fn capture(&self) -> Env {
+        let mut seen = HashSet::new();
+        let vars = self
+            .vars()
+            .into_iter()
+            .filter(|(s, _, _)| seen.insert(s.clone()))
+            .collect();
+        Env {
+            frame: Rc::new(Frame {
+                vars: RefCell::new(vars),
+                parent: None,
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Declare the types and functions in a block before running any of it, as
+    /// `Checker::hoist` does.
+    pub fn hoist(&mut self, stmts: &[AstNode]) {
+        for e in stmts {
+            match &e.kind {
+                AstKind::TypeDecl { name, ty } => self.types.declare(name, ty),
+                AstKind::Function { name, .. } => {
+                    self.fns.insert(name.clone(), e.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// A type as written in the program, with the names in it resolved.
+    fn resolve(&self, ty: &Type) -> Type {
+        self.types.resolve(ty).expect("checked by typeck")
+    }
+
+    /// Arguments of a function, with the types of each resolved.
+    fn resolve_params(&self, params: &[Param]) -> Vec<Param> {
+        params
+            .iter()
+            .map(|param| Param {
+                ty: self.resolve(&param.ty),
+                ..param.clone()
+            })
+            .collect()
+    }
+
+    /// A type checker which knows about everything in scope.
+    pub fn checker(&self) -> Checker {
+        let mut checker = Checker::default();
+        checker.use_types(&self.types);
+        for (ident, ty, _) in self.vars().iter().rev() {
+            checker.bind(ident, ty);
+        }
+        for f in self.fns() {
+            if let AstKind::Function {
+                name, args, out, ..
+            } = &f.kind
+            {
+                checker.declare_fn(name, &self.resolve_params(args), &self.resolve(out));
+            }
+        }
+        checker
+    }
+
+    /// Apply `f` to the most recent binding of `var`, if there is one.
+    fn with_var<T>(&self, var: &str, f: impl FnOnce(&mut (String, Type, Value)) -> T) -> Option<T> {
+        let frame = self
+            .frames()
+            .find(|frame| frame.vars.borrow().iter().any(|(s, _, _)| var == s))?;
+        let mut vars = frame.vars.borrow_mut();
+        vars.iter_mut().find(|(s, _, _)| var == s).map(f)
+    }
 }
 
 fn envlookup(env: &Env, var: &str) -> Option<Value> {
-    env.vars
+    env.with_var(var, |(_, _, v)| v.clone())
+}
+
+/// Type of an expression, as given by the type checker with everything in `env`.
+///
+/// Used for the names bound by `match`, which (unlike `let`) aren't given a type.
+fn static_type(exp: &AstNode, env: &Env) -> Type {
+    env.checker().type_of(exp).expect("checked by typeck")
+}
+
+fn spawn_error(err: SpawnError, span: Span) -> RuntimeError {
+    let kind = RuntimeErrorKind::CommandSpawn {
+        program: err.program,
+        message: err.error.to_string(),
+    };
+    RuntimeError::new(kind, span)
+}
+
+fn command_failed(failure: &Failure, stderr: &[u8], span: Span) -> RuntimeError {
+    let kind = RuntimeErrorKind::CommandFailed {
+        program: failure.program.clone(),
+        args: failure.args.clone(),
+        status: failure.status,
+        stderr: String::from_utf8_lossy(stderr).into_owned(),
+    };
+    RuntimeError::new(kind, span)
+}
+
+/// Report a failed command as an error, unless failure is allowed by `errexit(false)`, which
+/// doesn't apply to commands checked with `?`.
+fn check_status(
+    failure: Option<&Failure>,
+    stderr: &[u8],
+    checked: bool,
+    env: &Env,
+    span: Span,
+) -> Result<(), RuntimeError> {
+    match failure {
+        Some(failure) if checked || !env.allow_fail => Err(command_failed(failure, stderr, span)),
+        _ => Ok(()),
+    }
+}
+
+/// Convert a value to the given type, reporting the value and type on failure.
+fn convert(val: Value, ty: &Type, env: &Env, span: Span) -> Result<Value, RuntimeError> {
+    let ty = ty.unfold();
+    // Commands are run here rather than by Value::convert, so a failure to start them can be
+    // reported as such. Failing commands are an error, unless they're converted to a type
+    // which includes the exit status and weren't checked with `?`.
+    let must_succeed = !matches!(ty, Type::Output | Type::Record(_));
+    let val = match (val, ty) {
+        // run for its effects only, as if it were a statement
+        (val @ Value::Command(_), Type::Unit) => {
+            run_effects(&val, env, span)?;
+            Value::Unit
+        }
+        (Value::Command(pipeline), Type::Process) => {
+            Value::Process(pipeline.start().map_err(|e| spawn_error(e, span))?)
+        }
+        (Value::Command(pipeline), ty) if *ty != Type::Command => {
+            // stderr is only captured when it can be part of the result
+            let output = pipeline
+                .output(!must_succeed)
+                .map_err(|e| spawn_error(e, span))?;
+            if must_succeed || pipeline.checked {
+                let failure = output.failure.as_ref();
+                check_status(failure, &output.stderr, pipeline.checked, env, span)?;
+            }
+            Value::Output(Box::new(output))
+        }
+        (Value::Process(process), ty) if *ty != Type::Process => {
+            let output = process.wait();
+            if must_succeed || process.pipeline().checked {
+                let (failure, checked) = (output.failure.as_ref(), process.pipeline().checked);
+                check_status(failure, &output.stderr, checked, env, span)?;
+            }
+            Value::Output(Box::new(output))
+        }
+        (val, _) => val,
+    };
+    val.clone().convert(ty).ok_or_else(|| {
+        let kind = RuntimeErrorKind::ConversionFailed {
+            value: val,
+            ty: ty.clone(),
+        };
+        RuntimeError::new(kind, span)
+    })
+}
+
+/// Evaluate an expression and convert it to a string, such as for interpolation.
+fn eval_string(exp: &AstNode, env: &Env) -> Result<String, RuntimeError> {
+    let val = eval_env(exp, env)?;
+    match convert(val, &Type::Str, env, exp.span)? {
+        Value::Str(s) => Ok(s),
+        _ => unreachable!(),
+    }
+}
+
+/// Evaluate a command token, concatenating its parts.
+fn eval_token(tok: &CommandToken, env: &Env) -> Result<String, RuntimeError> {
+    let parts = tok
+        .parts
         .iter()
-        .find(|(s, _, _)| (var == s))
-        .map(|(_, _, v)| v.clone())
+        .map(|ast| eval_string(ast, env))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.concat())
 }
 
-pub fn eval(exp: &AstNode) -> Value {
-    eval_env(
-        exp,
-        &Env {
-            vars: Vec::new(),
-            fns: HashMap::new(),
-        },
-    )
+/// Evaluate the target of `<<<`, where a `list<str>` is written to stdin one item per line.
+fn eval_stdin(tok: &CommandToken, env: &Env) -> Result<String, RuntimeError> {
+    if let [part] = tok.parts.as_slice() {
+        if let Value::List(xs) = eval_env(part, env)? {
+            let mut lines = String::new();
+            for x in xs {
+                let Value::Str(line) = convert(x, &Type::Str, env, part.span)? else {
+                    unreachable!()
+                };
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+            return Ok(lines);
+        }
+    }
+    eval_token(tok, env)
 }
 
-pub fn eval_env(exp: &AstNode, env: &Env) -> Value {
-    match exp {
-        AstNode::InfixExpr { verb, lhs, rhs } => {
-            let v1 = eval_env(lhs, env);
-            let v2 = eval_env(rhs, env);
-            let out = match verb {
-                InfixVerb::Plus => match (v1, v2) {
-                    (Value::Int(x1), Value::Int(x2)) => Value::Int(x1 + x2),
-                    _ => todo!(),
+/// Evaluate a whole program. Its last statement is run like any other, even though it's the
+/// value of the program.
+pub fn eval(exp: &AstNode) -> Result<Value, RuntimeError> {
+    let env = Env::default();
+    let out = eval_env(exp, &env)?;
+    run_effects(&out, &env, exp.span)?;
+    Ok(out)
+}
+
+/// Evaluate a single statement of a block, updating `env` with any new bindings.
+pub fn eval_stmt(e: &AstNode, env: &mut Env) -> Result<Value, RuntimeError> {
+    // Match over the AstNode for constructs which alter the environment,
+    // such as variable bindings, assignment, functions, or type aliases.
+    // In the default case, it just evaluates the expression.
+    let out = match &e.kind {
+        AstKind::Binding { pattern, ty, expr } => {
+            let ty = &env.resolve(ty);
+            let v = convert(eval_env(expr, env)?, ty, env, expr.span)?;
+            env.bind_pattern(pattern, ty, v)
+                .map_err(|kind| RuntimeError::new(kind, expr.span))?;
+            Value::Unit
+        }
+        AstKind::Assign { ident, expr } => {
+            let new_v = eval_env(expr, env)?;
+            let Some(ty) = env.with_var(ident, |(_, t, _)| t.clone()) else {
+                let kind = RuntimeErrorKind::AssignToUnbound(ident.clone());
+                return Err(RuntimeError::new(kind, e.span));
+            };
+            let new_v = convert(new_v, &ty, env, expr.span)?;
+            env.with_var(ident, |(_, _, v)| *v = new_v);
+            Value::Unit
+        }
+        // declared along with the rest of the block
+        AstKind::Function { .. } => Value::Unit,
+        // a setting for the rest of the block, like `set +e` in bash
+        AstKind::Call { name, args, .. } if name == "errexit" => {
+            let [arg] = args.as_slice() else {
+                unreachable!("checked by typeck")
+            };
+            match eval_env(arg, env)? {
+                Value::Bool(b) => env.allow_fail = !b,
+                value => {
+                    let kind = RuntimeErrorKind::ConversionFailed {
+                        value,
+                        ty: Type::Bool,
+                    };
+                    return Err(RuntimeError::new(kind, arg.span));
+                }
+            }
+            Value::Unit
+        }
+        AstKind::While { cond, block } => {
+            loop {
+                match eval_env(cond, env)? {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => break,
+                    out => {
+                        let kind = RuntimeErrorKind::NonBoolCondition(out);
+                        return Err(RuntimeError::new(kind, cond.span));
+                    }
+                }
+                if !eval_loop_body(block, env.child())? {
+                    break;
+                }
+            }
+            Value::Unit
+        }
+        AstKind::For {
+            pattern,
+            ty,
+            iter,
+            block,
+        } => {
+            let ty = &env.resolve(ty);
+            let items: Box<dyn Iterator<Item = Value>> = match &iter.kind {
+                // counted through rather than made into a list, which could be huge
+                AstKind::InfixExpr {
+                    verb: InfixVerb::Range,
+                    lhs,
+                    rhs,
+                } => match (eval_env(lhs, env)?, eval_env(rhs, env)?) {
+                    (Value::Int(start), Value::Int(end)) => Box::new((start..end).map(Value::Int)),
+                    (lhs, rhs) => {
+                        let verb = InfixVerb::Range;
+                        let kind = RuntimeErrorKind::InvalidOperands { verb, lhs, rhs };
+                        return Err(RuntimeError::new(kind, iter.span));
+                    }
                 },
-                InfixVerb::Minus => todo!(),
-                InfixVerb::Times => todo!(),
-                InfixVerb::Divide => todo!(),
+                _ => Box::new(eval_items(eval_env(iter, env)?, env, iter.span)?.into_iter()),
             };
-            out
+            for item in items {
+                let item = convert(item, ty, env, iter.span)?;
+                let body_env = env.child();
+                body_env
+                    .bind_pattern(pattern, ty, item)
+                    .map_err(|kind| RuntimeError::new(kind, iter.span))?;
+                if !eval_loop_body(block, body_env)? {
+                    break;
+                }
+            }
+            Value::Unit
+        }
+        // declared along with the rest of the block
+        AstKind::TypeDecl { .. } => Value::Unit,
+        AstKind::Break => return Err(RuntimeError::new(RuntimeErrorKind::Break, e.span)),
+        AstKind::Continue => return Err(RuntimeError::new(RuntimeErrorKind::Continue, e.span)),
+        AstKind::Return(expr) => {
+            let kind = RuntimeErrorKind::Return(eval_env(expr, env)?);
+            return Err(RuntimeError::new(kind, e.span));
+        }
+        _ => eval_env(e, env)?,
+    };
+    Ok(out)
+}
+
+/// Run one iteration of a loop in `body_env`, which has the loop variables (if any) bound.
+///
+/// Returns whether the loop should keep going, i.e. it didn't `break`.
+fn eval_loop_body(block: &AstNode, mut body_env: Env) -> Result<bool, RuntimeError> {
+    let AstKind::Block(es) = &block.kind else {
+        unreachable!("loop bodies are always blocks")
+    };
+    body_env.hoist(es);
+    let result = es.iter().try_for_each(|e| {
+        let out = eval_stmt(e, &mut body_env)?;
+        run_effects(&out, &body_env, e.span)
+    });
+    match result {
+        Ok(()) => Ok(true),
+        Err(err) => match *err.kind {
+            RuntimeErrorKind::Break => Ok(false),
+            RuntimeErrorKind::Continue => Ok(true),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Items iterated over by `for`: the items of a list, the keys of a map, or each line of a
+/// command's stdout.
+fn eval_items(val: Value, env: &Env, span: Span) -> Result<Vec<Value>, RuntimeError> {
+    let output = match val {
+        Value::List(xs) => return Ok(xs),
+        Value::Map(map) => return Ok(map.keys()),
+        Value::Command(pipeline) => {
+            let output = pipeline.output(false).map_err(|e| spawn_error(e, span))?;
+            let failure = output.failure.as_ref();
+            check_status(failure, &output.stderr, pipeline.checked, env, span)?;
+            output
         }
-        AstNode::Integer(x) => Value::Int(*x),
-        AstNode::Boolean(b) => Value::Bool(*b),
-        AstNode::Ident(var) => envlookup(&env, var).expect("identifier not found"),
-        AstNode::Command(tokens) => {
+        Value::Process(process) => {
+            let output = process.wait();
+            let (failure, checked) = (output.failure.as_ref(), process.pipeline().checked);
+            check_status(failure, &output.stderr, checked, env, span)?;
+            output
+        }
+        Value::Output(output) => *output,
+        value => {
+            let kind = RuntimeErrorKind::ConversionFailed {
+                value,
+                ty: Type::List(Box::new(Type::Str)),
+            };
+            return Err(RuntimeError::new(kind, span));
+        }
+    };
+    let stdout = output.stdout_str();
+    Ok(stdout
+        .lines()
+        .map(|line| Value::Str(line.to_string()))
+        .collect())
+}
+
+/// If a value in statement position has some defined side effect (such as a command)
+/// then it is acted upon here.
+pub fn run_effects(val: &Value, env: &Env, span: Span) -> Result<(), RuntimeError> {
+    if let Value::Command(pipeline) = val {
+        let failure = pipeline.run().map_err(|e| spawn_error(e, span))?;
+        check_status(failure.as_ref(), &[], pipeline.checked, env, span)?;
+    }
+    Ok(())
+}
+
+pub fn eval_env(exp: &AstNode, env: &Env) -> Result<Value, RuntimeError> {
+    let error = |kind| Err(RuntimeError::new(kind, exp.span));
+    match &exp.kind {
+        AstKind::PrefixExpr { verb, expr } => match (verb, eval_env(expr, env)?) {
+            (PrefixVerb::Negate, Value::Int(x)) => match x.checked_neg() {
+                Some(x) => Ok(Value::Int(x)),
+                None => error(RuntimeErrorKind::IntegerOverflow(format!("-({x})"))),
+            },
+            (PrefixVerb::Negate, Value::Float(x)) => Ok(Value::Float(-x)),
+            (PrefixVerb::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+            (verb, value) => error(RuntimeErrorKind::InvalidOperand {
+                verb: verb.clone(),
+                value,
+            }),
+        },
+        AstKind::InfixExpr { verb, lhs, rhs } => {
+            let v1 = eval_env(lhs, env)?;
+            // && and || short-circuit, so only evaluate rhs if it's needed
+            match (verb, &v1) {
+                (InfixVerb::And, Value::Bool(false)) => return Ok(Value::Bool(false)),
+                (InfixVerb::Or, Value::Bool(true)) => return Ok(Value::Bool(true)),
+                _ => {}
+            }
+            let v2 = eval_env(rhs, env)?;
+            eval_infix(verb, v1, v2).map_err(|kind| RuntimeError::new(kind, exp.span))
+        }
+        AstKind::Integer(x) => Ok(Value::Int(*x)),
+        AstKind::Float(x) => Ok(Value::Float(*x)),
+        AstKind::Format { expr, precision } => {
+            match convert(eval_env(expr, env)?, &Type::Float, env, expr.span)? {
+                Value::Float(x) => Ok(Value::Str(format!("{x:.precision$}"))),
+                _ => unreachable!(),
+            }
+        }
+        AstKind::Boolean(b) => Ok(Value::Bool(*b)),
+        AstKind::Ident(var) => match envlookup(env, var) {
+            Some(v) => Ok(v),
+            None => match env.fns.get(var).map(|f| &f.kind) {
+                // a function used as a value, such as to pass it to another function
+                Some(AstKind::Function {
+                    args, out, block, ..
+                }) => Ok(Value::Fn(Rc::new(Closure {
+                    args: env.resolve_params(args),
+                    out: env.resolve(out),
+                    block: (**block).clone(),
+                    env: env.fn_env(),
+                }))),
+                _ => error(RuntimeErrorKind::UnknownIdentifier(var.clone())),
+            },
+        },
+        AstKind::Command { tokens, redirects } => {
             // evaluate each token down to a string with concatenated parts
             let mut args: Vec<String> = Vec::new();
 
             for tok in tokens {
-                let parts: Vec<String> = tok
-                    .0
-                    .iter()
-                    .map(|ast| {
-                        let val = eval_env(&ast, env);
-                        let Some(Value::Str(s)) = val.convert(&Type::Str) else {
-                            panic!()
-                        };
-                        s
-                    })
-                    .collect();
-                args.push(parts.concat());
+                args.push(eval_token(tok, env)?);
             }
 
+            // commands have to have at least one token (i.e. the program)
             let Some((program, args)) = args.split_first() else {
-                // commands have to have at least one token (i.e. the program)
-                todo!();
+                return error(RuntimeErrorKind::EmptyCommand);
             };
-            // let mut cmd = Command::new(program);
-            // cmd.args(args);
-
-            let val_program = Value::Str(program.clone());
-            let val_args = Value::List(args.iter().map(|a| Value::Str(a.clone())).collect());
-
-            Value::Record(HashMap::from_iter([
-                (String::from("program"), val_program),
-                (String::from("args"), val_args),
-                (String::from("_command"), Value::Unit),
-                // TODO: stdin,stdout,stderr ???
-            ]))
-            // Value::Command {
-            // command: Rc::new(RefCell::new(cmd)),
-            // },
-        }
-        AstNode::Block(es) => {
-            let mut out = Value::Unit;
-            let mut block_env = env.clone();
-            for e in es {
-                // Match over the AstNode for constructs which alter the environment,
-                // such as variable bindings, assignment, functions, or type aliases.
-                // In the default case, it just evaluates the expression.
-                out = match e {
-                    AstNode::Binding { ident, ty, expr } => {
-                        let v = eval_env(expr, &block_env);
-                        let Some(v) = v.convert(ty) else {
-                            todo!("failed to convert");
-                        };
-                        block_env
-                            .vars
-                            .insert(0, (ident.to_string(), ty.clone(), v.clone()));
-                        Value::Unit
-                    }
-                    AstNode::Assign { ident, expr } => {
-                        if envlookup(&block_env, ident).is_none() {
-                            todo!()
-                        }
-                        let new_v = eval_env(expr, &block_env);
-                        for (s, _t, v) in block_env.vars.iter_mut() {
-                            if ident == s {
-                                *v = new_v;
-                                break;
-                            }
-                        }
-                        Value::Unit
-                    }
-                    AstNode::Function { name, .. } => {
-                        block_env.fns.insert(name.clone(), e.clone());
-                        Value::Unit
-                    }
-                    _ => eval_env(e, &block_env),
+
+            let mut stage = Stage {
+                program: program.clone(),
+                args: args.to_vec(),
+                redirects: Vec::new(),
+                allow_fail: false,
+            };
+            for redirect in redirects {
+                let target = match (&redirect.op, &redirect.target) {
+                    (RedirectOp::StdinValue, Some(tok)) => eval_stdin(tok, env)?,
+                    (_, Some(tok)) => eval_token(tok, env)?,
+                    (_, None) => String::new(),
                 };
+                stage.redirects.push(Redirect {
+                    op: redirect.op,
+                    target,
+                });
+            }
+            Ok(Value::Command(Pipeline {
+                stages: vec![stage],
+                checked: false,
+            }))
+        }
+        AstKind::Block(es) => {
+            let mut out = Value::Unit;
+            let mut block_env = env.child();
+            block_env.hoist(es);
+            for (i, e) in es.iter().enumerate() {
+                out = eval_stmt(e, &mut block_env)?;
 
-                // If the resulting value has some defined side effect (such as a command record)
-                // then it should be acted upon here.
-                if let Some((program, args)) = extract_command(&out) {
-                    let _ = Command::new(program).args(args).spawn().unwrap().wait();
+                // The last node is the value of the block, so it's left for the caller to use.
+                if i + 1 < es.len() {
+                    run_effects(&out, &block_env, e.span)?;
                 }
             }
-            out
+            Ok(out)
+        }
+        AstKind::Assign { .. } => unreachable!(),
+        AstKind::Binding { .. } => unreachable!(),
+        AstKind::Function { .. } => unreachable!(),
+        AstKind::TypeDecl { .. } => unreachable!(),
+        AstKind::While { .. } | AstKind::For { .. } | AstKind::Break | AstKind::Continue => {
+            unreachable!("loops are handled by eval_stmt")
         }
-        AstNode::Assign { .. } => unreachable!(),
-        AstNode::Binding { .. } => unreachable!(),
-        AstNode::Function { .. } => unreachable!(),
-        AstNode::QuoteString(qs) => {
-            let parts: Vec<String> = qs
+        AstKind::Return(_) => unreachable!(),
+        AstKind::QuoteString(qs) => {
+            let parts = qs
                 .iter()
-                .map(|ast| {
-                    let val = eval_env(&ast, env);
-                    let Some(Value::Str(s)) = val.convert(&Type::Str) else {
-                        panic!()
-                    };
-                    s
-                })
-                .collect();
-            Value::Str(parts.concat())
+                .map(|ast| eval_string(ast, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Str(parts.concat()))
         }
-        AstNode::StringLiteral(s) => Value::Str(String::from(s)),
-        AstNode::Unit => todo!(),
-        AstNode::IfThenElse {
+        AstKind::StringLiteral(s) => Ok(Value::Str(String::from(s))),
+        AstKind::Unit => Ok(Value::Unit),
+        AstKind::IfThenElse {
             cond,
             t_block,
             f_block,
-        } => {
-            let out = eval_env(cond, env);
-            let Value::Bool(b) = out else {
-                todo!();
+        } => match eval_env(cond, env)? {
+            Value::Bool(true) => eval_env(t_block, env),
+            Value::Bool(false) => eval_env(f_block, env),
+            out => Err(RuntimeError::new(
+                RuntimeErrorKind::NonBoolCondition(out),
+                cond.span,
+            )),
+        },
+        AstKind::TryCatch {
+            block,
+            ident,
+            catch,
+        } => match eval_env(block, env) {
+            Ok(v) => Ok(v),
+            Err(err) if err.is_control_flow() => Err(err),
+            Err(err) => {
+                let catch_env = env.child();
+                catch_env.bind(ident, &Type::error_record(), err.to_value());
+                eval_env(catch, &catch_env)
+            }
+        },
+        AstKind::Propagate(expr) => match eval_env(expr, env)? {
+            Value::Command(pipeline) => Ok(Value::Command(Pipeline {
+                checked: true,
+                ..pipeline
+            })),
+            Value::Process(process) => {
+                let output = process.wait();
+                match &output.failure {
+                    Some(failure) => Err(command_failed(failure, &output.stderr, exp.span)),
+                    None => Ok(Value::Output(Box::new(output))),
+                }
+            }
+            Value::Output(output) => match &output.failure {
+                Some(failure) => Err(command_failed(failure, &output.stderr, exp.span)),
+                None => Ok(Value::Output(output)),
+            },
+            value => error(RuntimeErrorKind::ConversionFailed {
+                value,
+                ty: Type::Output,
+            }),
+        },
+        AstKind::ListValue(es) => {
+            let xs = es
+                .iter()
+                .map(|e| eval_env(e, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::List(xs))
+        }
+        AstKind::TupleValue(es) => {
+            let xs = es
+                .iter()
+                .map(|e| eval_env(e, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Tuple(xs))
+        }
+        AstKind::TupleIndex { expr, index } => match eval_env(expr, env)? {
+            Value::Tuple(mut xs) if *index < xs.len() => Ok(xs.swap_remove(*index)),
+            _ => unreachable!("checked by typeck"),
+        },
+        AstKind::Index { expr, index } => {
+            let xs = eval_list(expr, env)?;
+            let index = eval_int(index, env)?;
+            match list_position(index, xs.len()).filter(|i| *i < xs.len()) {
+                Some(i) => Ok(xs[i].clone()),
+                None => error(RuntimeErrorKind::IndexOutOfBounds {
+                    index,
+                    len: xs.len(),
+                }),
+            }
+        }
+        AstKind::Slice { expr, start, end } => {
+            let xs = eval_list(expr, env)?;
+            let (start, end) = (eval_int(start, env)?, eval_int(end, env)?);
+            let len = xs.len();
+            match (list_position(start, len), list_position(end, len)) {
+                (Some(i), Some(j)) if i <= j && j <= len => Ok(Value::List(xs[i..j].to_vec())),
+                _ => error(RuntimeErrorKind::SliceOutOfBounds { start, end, len }),
+            }
+        }
+        AstKind::MapValue(entries) => {
+            let mut map = Map::default();
+            for (key, value) in entries {
+                map.insert(eval_env(key, env)?, eval_env(value, env)?);
+            }
+            Ok(Value::Map(map))
+        }
+        // replaced fields are converted to their type in the original record
+        AstKind::RecordUpdate { base, fields } => {
+            let Type::Record(types) = static_type(base, env) else {
+                unreachable!("checked by typeck")
             };
-            let out = if b {
-                eval_env(t_block, env)
-            } else {
-                eval_env(f_block, env)
+            let Value::Record(mut out) = eval_env(base, env)? else {
+                unreachable!("checked by typeck")
             };
-            out
+            for (name, e) in fields {
+                let val = eval_env(e, env)?;
+                let val = match types.get(name) {
+                    Some(ty) => convert(val, ty, env, e.span)?,
+                    None => val,
+                };
+                out.insert(name.clone(), val);
+            }
+            Ok(Value::Record(out))
+        }
+        // commands (and processes) are run for the fields of their output
+        AstKind::Field { expr, name } => {
+            let record = match eval_env(expr, env)? {
+                val @ Value::Record(_) => val,
+                val => convert(val, &Type::output_record(), env, expr.span)?,
+            };
+            match record {
+                Value::Record(mut fields) => Ok(fields.remove(name).expect("checked by typeck")),
+                _ => unreachable!("checked by typeck"),
+            }
+        }
+        AstKind::VariantValue { tag, expr } => {
+            let val = eval_env(expr, env)?;
+            Ok(Value::Variant(tag.clone(), Box::new(val)))
         }
-        AstNode::RecordValue(r) => {
+        // the first arm whose pattern matches (and whose guard is true) is taken
+        AstKind::Match { expr, arms } => {
+            let val = eval_env(expr, env)?;
+            let ty = static_type(expr, env);
+            for arm in arms {
+                let arm_env = env.child();
+                if arm_env
+                    .bind_pattern(&arm.pattern, &ty, val.clone())
+                    .is_err()
+                {
+                    continue;
+                }
+                if let Some(guard) = &arm.guard {
+                    match eval_env(guard, &arm_env)? {
+                        Value::Bool(true) => {}
+                        Value::Bool(false) => continue,
+                        out => {
+                            let kind = RuntimeErrorKind::NonBoolCondition(out);
+                            return Err(RuntimeError::new(kind, guard.span));
+                        }
+                    }
+                }
+                return eval_env(&arm.body, &arm_env);
+            }
+            unreachable!("checked by typeck")
+        }
+        AstKind::RecordValue(r) => {
             let mut out = HashMap::new();
             for (key, ast) in r.iter() {
-                let val = eval_env(ast, env);
+                let val = eval_env(ast, env)?;
                 out.insert(key.clone(), val);
             }
-            Value::Record(out)
+            Ok(Value::Record(out))
         }
-        AstNode::Call { name, args } => match (name.as_str(), args.as_slice()) {
+        AstKind::Call { name, args, named } => match (name.as_str(), args.as_slice()) {
+            // builtins only take positional arguments, as in Checker::call
+            _ if !named.is_empty() => call_named(name, args, named, env, exp.span),
             ("display", [e]) => {
-                let out = eval_env(e, env);
-                Value::Str(macro_display(out))
-            }
-            (name, args) => match env.fns.get(name) {
-                Some(AstNode::Function {
-                    name: _,
-                    args: fn_args,
-                    out: fn_out,
-                    block,
-                }) => {
-                    let mut fn_env = Env {
-                        vars: Vec::new(),
-                        fns: env.fns.clone(),
-                    };
-                    for i in 0..args.len() {
-                        let v = eval_env(&args[i], env);
-                        fn_env
-                            .vars
-                            .push((fn_args[i].0.clone(), fn_args[i].1.clone(), v));
-                    }
-
-                    let v = eval_env(block, &fn_env);
-                    v.convert(fn_out).unwrap()
-                }
-                Some(_) => {
-                    todo!()
+                let out = eval_env(e, env)?;
+                Ok(Value::Str(macro_display(out)))
+            }
+            ("len", [e]) => match eval_env(e, env)? {
+                Value::List(xs) => Ok(Value::Int(xs.len() as i64)),
+                Value::Map(map) => Ok(Value::Int(map.len() as i64)),
+                Value::Str(s) => Ok(Value::Int(s.chars().count() as i64)),
+                value => error(RuntimeErrorKind::ConversionFailed {
+                    value,
+                    ty: Type::List(Box::new(Type::Str)),
+                }),
+            },
+            ("lookup", [m, key, default @ ..]) => {
+                let map = eval_map(m, env)?;
+                let key = eval_env(key, env)?;
+                match (map.get(&key), default) {
+                    (Some(value), _) => Ok(value.clone()),
+                    (None, [default]) => eval_env(default, env),
+                    (None, _) => error(RuntimeErrorKind::KeyNotFound(key)),
                 }
-                None => {
-                    todo!()
+            }
+            ("contains", [xs, x]) => {
+                let x = eval_env(x, env)?;
+                match eval_env(xs, env)? {
+                    Value::Map(map) => Ok(Value::Bool(map.get(&x).is_some())),
+                    Value::List(xs) => Ok(Value::Bool(xs.contains(&x))),
+                    value => error(RuntimeErrorKind::ConversionFailed {
+                        value,
+                        ty: Type::List(Box::new(Type::Str)),
+                    }),
                 }
+            }
+            ("keys", [m]) => Ok(Value::List(eval_map(m, env)?.keys())),
+            ("values", [m]) => Ok(Value::List(eval_map(m, env)?.values())),
+            ("insert", [m, key, value]) => {
+                let (var, key_ty, value_ty) = map_var(m, env)?;
+                let key = convert(eval_env(key, env)?, &key_ty, env, key.span)?;
+                let value = convert(eval_env(value, env)?, &value_ty, env, value.span)?;
+                env.with_var(&var, |(_, _, map)| {
+                    if let Value::Map(map) = map {
+                        map.insert(key, value);
+                    }
+                });
+                Ok(Value::Unit)
+            }
+            ("remove", [m, key]) => {
+                let (var, key_ty, _) = map_var(m, env)?;
+                let key = convert(eval_env(key, env)?, &key_ty, env, key.span)?;
+                env.with_var(&var, |(_, _, map)| {
+                    if let Value::Map(map) = map {
+                        map.remove(&key);
+                    }
+                });
+                Ok(Value::Unit)
+            }
+            ("allow_fail", [e]) => match eval_env(e, env)? {
+                Value::Command(pipeline) => Ok(Value::Command(pipeline.allow_fail())),
+                value => error(RuntimeErrorKind::ConversionFailed {
+                    value,
+                    ty: Type::Command,
+                }),
             },
+            // a variable holding a function shadows a function with its name, see Checker::call
+            (name, args) => call_named(name, args, named, env, exp.span),
+        },
+        AstKind::Closure { args, out, block } => {
+            let args = args
+                .iter()
+                .map(|(pattern, ty)| Param {
+                    pattern: pattern.clone(),
+                    ty: env.resolve(ty),
+                    default: None,
+                })
+                .collect();
+            Ok(Value::Fn(Rc::new(Closure {
+                args,
+                out: env.resolve(out),
+                block: (**block).clone(),
+                env: env.capture(),
+            })))
+        }
+        AstKind::Apply { expr, args } => match eval_env(expr, env)? {
+            Value::Fn(f) => call_closure(&f.ty().to_string(), &f, args, &[], env, exp.span),
+            _ => unreachable!("checked by typeck"),
+        },
+    }
+}
+
+/// Call a user defined function or a variable holding a function value by its name.
+fn call_named(
+    name: &str,
+    args: &[AstNode],
+    named: &[(String, AstNode)],
+    env: &Env,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    let error = |kind| Err(RuntimeError::new(kind, span));
+    // a variable holding a function shadows a function with its name, see Checker::call
+    if let Some(Value::Fn(f)) = envlookup(env, name) {
+        return call_closure(name, &f, args, named, env, span);
+    }
+    match env.fns.get(name).map(|f| &f.kind) {
+        Some(AstKind::Function {
+            name,
+            args: params,
+            out,
+            block,
+        }) => {
+            let callee = Callee {
+                name,
+                params: &env.resolve_params(params),
+                out: &env.resolve(out),
+                block,
+            };
+            call_fn(callee, env.fn_env(), args, named, env, span)
+        }
+        Some(_) => error(RuntimeErrorKind::NotAFunction(name.to_string())),
+        None => error(RuntimeErrorKind::UnknownFunction(name.to_string())),
+    }
+}
+
+/// A function being called, with the types of its arguments and output resolved.
+#[derive(Clone, Copy)]
+struct Callee<'a> {
+    /// How the function was called, for errors.
+    name: &'a str,
+    params: &'a [Param],
+    out: &'a Type,
+    block: &'a AstNode,
+}
+
+/// Call a function, evaluating its arguments in the caller's `env`. The body is evaluated in
+/// `fn_env`, which has whatever it can see other than its arguments.
+///
+/// Arguments left out are given their defaults, which are evaluated in `fn_env` once the
+/// arguments before them are bound.
+fn call_fn(
+    f: Callee,
+    fn_env: Env,
+    args: &[AstNode],
+    named: &[(String, AstNode)],
+    env: &Env,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    let invalid = |message: String| {
+        let kind = RuntimeErrorKind::InvalidArguments {
+            function: f.name.to_string(),
+            message,
+        };
+        Err(RuntimeError::new(kind, span))
+    };
+    if args.len() > f.params.len() {
+        let message = format!("expected at most {} argument(s)", f.params.len());
+        return invalid(message);
+    }
+
+    // evaluated in the order they're written, before any of them are bound
+    let mut given: Vec<Option<(Value, Span)>> = vec![None; f.params.len()];
+    for (slot, arg) in given.iter_mut().zip(args) {
+        *slot = Some((eval_env(arg, env)?, arg.span));
+    }
+    for (name, arg) in named {
+        let pattern = Pattern::Ident(name.clone());
+        let Some(i) = f.params.iter().position(|p| p.pattern == pattern) else {
+            return invalid(format!("no argument `{name}`"));
+        };
+        if given[i].is_some() {
+            return invalid(format!("argument `{name}` is given more than once"));
+        }
+        given[i] = Some((eval_env(arg, env)?, arg.span));
+    }
+
+    for (param, given) in f.params.iter().zip(given) {
+        let v = match (given, &param.default) {
+            (Some((v, arg_span)), _) => convert(v, &param.ty, env, arg_span)?,
+            (None, Some(default)) => {
+                let v = eval_env(default, &fn_env)?;
+                convert(v, &param.ty, &fn_env, default.span)?
+            }
+            (None, None) => return invalid(format!("missing argument `{}`", param.pattern)),
+        };
+        fn_env
+            .bind_pattern(&param.pattern, &param.ty, v)
+            .map_err(|kind| RuntimeError::new(kind, span))?;
+    }
+    let v = match eval_env(f.block, &fn_env) {
+        Ok(v) => v,
+        Err(err) => match *err.kind {
+            RuntimeErrorKind::Return(v) => v,
+            kind => return Err(RuntimeError::new(kind, err.span)),
         },
+    };
+    convert(v, f.out, env, span)
+}
+
+fn call_closure(
+    name: &str,
+    f: &Closure,
+    args: &[AstNode],
+    named: &[(String, AstNode)],
+    env: &Env,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    // `errexit` is a setting of the caller, as with functions
+    let fn_env = Env {
+        allow_fail: env.allow_fail,
+        ..f.env.child()
+    };
+    let callee = Callee {
+        name,
+        params: &f.args,
+        out: &f.out,
+        block: &f.block,
+    };
+    call_fn(callee, fn_env, args, named, env, span)
+}
+
+/// Evaluate an expression which the type checker has made sure is a list.
+fn eval_list(exp: &AstNode, env: &Env) -> Result<Vec<Value>, RuntimeError> {
+    match eval_env(exp, env)? {
+        Value::List(xs) => Ok(xs),
+        value => {
+            let kind = RuntimeErrorKind::ConversionFailed {
+                value,
+                ty: Type::List(Box::new(Type::Str)),
+            };
+            Err(RuntimeError::new(kind, exp.span))
+        }
+    }
+}
+
+/// Evaluate an expression which the type checker has made sure is a map.
+fn eval_map(exp: &AstNode, env: &Env) -> Result<Map, RuntimeError> {
+    match eval_env(exp, env)? {
+        Value::Map(map) => Ok(map),
+        value => {
+            let kind = RuntimeErrorKind::ConversionFailed {
+                value,
+                ty: Type::Map(Box::new(Type::Str), Box::new(Type::Str)),
+            };
+            Err(RuntimeError::new(kind, exp.span))
+        }
     }
 }
 
-fn macro_display(v: Value) -> String {
+/// The variable updated by `insert` or `remove`, along with its key and value types.
+fn map_var(exp: &AstNode, env: &Env) -> Result<(String, Type, Type), RuntimeError> {
+    let AstKind::Ident(var) = &exp.kind else {
+        unreachable!("checked by typeck")
+    };
+    match env.with_var(var, |(_, ty, _)| ty.clone()) {
+        Some(Type::Map(key, value)) => Ok((var.clone(), *key, *value)),
+        Some(_) => Err(RuntimeError::new(
+            RuntimeErrorKind::ConversionFailed {
+                value: envlookup(env, var).expect("bound, as it has a type"),
+                ty: Type::Map(Box::new(Type::Str), Box::new(Type::Str)),
+            },
+            exp.span,
+        )),
+        None => Err(RuntimeError::new(
+            RuntimeErrorKind::UnknownIdentifier(var.clone()),
+            exp.span,
+        )),
+    }
+}
+
+fn eval_int(exp: &AstNode, env: &Env) -> Result<i64, RuntimeError> {
+    match convert(eval_env(exp, env)?, &Type::Int, env, exp.span)? {
+        Value::Int(x) => Ok(x),
+        _ => unreachable!(),
+    }
+}
+
+/// Position in a list of length `len` for an index, where negative indices count back from the
+/// end. The position may be past the end of the list.
+fn list_position(index: i64, len: usize) -> Option<usize> {
+    match index {
+        0.. => usize::try_from(index).ok(),
+        _ => len.checked_sub(usize::try_from(index.unsigned_abs()).ok()?),
+    }
+}
+
+fn eval_infix(verb: &InfixVerb, v1: Value, v2: Value) -> Result<Value, RuntimeErrorKind> {
+    use InfixVerb::*;
+
+    let overflow =
+        |x1: i64, x2: i64| RuntimeErrorKind::IntegerOverflow(format!("{x1} {verb} {x2}"));
+    // an int used with a float is converted to a float, see `infix_type`
+    let (v1, v2) = match (v1, v2) {
+        (Value::Int(x1), v2 @ Value::Float(_)) => (Value::Float(x1 as f64), v2),
+        (v1 @ Value::Float(_), Value::Int(x2)) => (v1, Value::Float(x2 as f64)),
+        vs => vs,
+    };
+    let out = match (verb, &v1, &v2) {
+        (Plus | Minus | Times, Value::Int(x1), Value::Int(x2)) => {
+            let out = match verb {
+                Plus => x1.checked_add(*x2),
+                Minus => x1.checked_sub(*x2),
+                Times => x1.checked_mul(*x2),
+                _ => unreachable!(),
+            };
+            Value::Int(out.ok_or_else(|| overflow(*x1, *x2))?)
+        }
+        (Divide | Modulo, Value::Int(_), Value::Int(0)) => {
+            return Err(RuntimeErrorKind::DivisionByZero);
+        }
+        (Divide, Value::Int(x1), Value::Int(x2)) => {
+            Value::Int(x1.checked_div(*x2).ok_or_else(|| overflow(*x1, *x2))?)
+        }
+        (Modulo, Value::Int(x1), Value::Int(x2)) => {
+            Value::Int(x1.checked_rem(*x2).ok_or_else(|| overflow(*x1, *x2))?)
+        }
+        (Less | Greater | LessEqual | GreaterEqual, Value::Int(x1), Value::Int(x2)) => {
+            Value::Bool(compare(verb, x1.cmp(x2)))
+        }
+        (Divide | Modulo, Value::Float(_), Value::Float(x2)) if *x2 == 0.0 => {
+            return Err(RuntimeErrorKind::DivisionByZero);
+        }
+        (Plus | Minus | Times | Divide | Modulo, Value::Float(x1), Value::Float(x2)) => {
+            let out = match verb {
+                Plus => x1 + x2,
+                Minus => x1 - x2,
+                Times => x1 * x2,
+                Divide => x1 / x2,
+                Modulo => x1 % x2,
+                _ => unreachable!(),
+            };
+            if !out.is_finite() {
+                let expr = format!("{} {verb} {}", float_string(*x1), float_string(*x2));
+                return Err(RuntimeErrorKind::FloatOverflow(expr));
+            }
+            Value::Float(out)
+        }
+        (Less | Greater | LessEqual | GreaterEqual, Value::Float(x1), Value::Float(x2)) => {
+            Value::Bool(compare(
+                verb,
+                x1.partial_cmp(x2).expect("floats are finite"),
+            ))
+        }
+
+        (Plus, Value::Str(s1), Value::Str(s2)) => Value::Str(format!("{s1}{s2}")),
+        (Less | Greater | LessEqual | GreaterEqual, Value::Str(s1), Value::Str(s2)) => {
+            Value::Bool(compare(verb, s1.cmp(s2)))
+        }
+
+        (And, Value::Bool(b1), Value::Bool(b2)) => Value::Bool(*b1 && *b2),
+        (Or, Value::Bool(b1), Value::Bool(b2)) => Value::Bool(*b1 || *b2),
+
+        (Plus, Value::List(xs), Value::List(ys)) => {
+            Value::List(xs.iter().chain(ys.iter()).cloned().collect())
+        }
+
+        (Pipe, Value::Command(p1), Value::Command(p2)) => {
+            Value::Command(p1.clone().pipe(p2.clone()))
+        }
+        (Range, Value::Int(x1), Value::Int(x2)) => {
+            let too_large = RuntimeErrorKind::RangeTooLarge {
+                start: *x1,
+                end: *x2,
+            };
+            let len = x2.checked_sub(*x1).ok_or(too_large.clone())?.max(0);
+            let mut xs = Vec::new();
+            usize::try_from(len)
+                .ok()
+                .and_then(|len| xs.try_reserve_exact(len).ok())
+                .ok_or(too_large)?;
+            xs.extend((*x1..*x2).map(Value::Int));
+            Value::List(xs)
+        }
+
+        // any two values of the same kind can be checked for equality
+        (Equal | NotEqual, _, _) if std::mem::discriminant(&v1) == std::mem::discriminant(&v2) => {
+            Value::Bool((v1 == v2) == matches!(verb, Equal))
+        }
+        _ => {
+            return Err(RuntimeErrorKind::InvalidOperands {
+                verb: verb.clone(),
+                lhs: v1,
+                rhs: v2,
+            })
+        }
+    };
+    Ok(out)
+}
+
+/// Whether the ordering satisfies a comparison verb such as `<=`.
+fn compare(verb: &InfixVerb, ord: std::cmp::Ordering) -> bool {
+    match verb {
+        InfixVerb::Less => ord.is_lt(),
+        InfixVerb::Greater => ord.is_gt(),
+        InfixVerb::LessEqual => ord.is_le(),
+        InfixVerb::GreaterEqual => ord.is_ge(),
+        _ => unreachable!(),
+    }
+}
+
+/// A float as it's shown in a string, which always has a decimal point (or an exponent, for
+/// very large and small floats) so it isn't mistaken for an int.
+fn float_string(x: f64) -> String {
+    format!("{x:?}")
+}
+
+pub fn macro_display(v: Value) -> String {
     match v {
         Value::Int(x) => x.to_string(),
+        Value::Float(x) => float_string(x),
         Value::Str(x) => x,
         Value::Bool(b) => (match b {
             true => "true",
             false => "false",
         })
         .into(),
-        Value::List(xs) => {
+        Value::List(xs) | Value::Tuple(xs) => {
             let ys: Vec<_> = xs.into_iter().map(macro_display).collect();
             ys.join(" ")
         }
         Value::Unit => "unit".into(),
         Value::Record(kv) => format!("{:?}", kv),
+        // written as it would be constructed, with `[none:]` for a tag without a value
+        Value::Variant(tag, val) => match *val {
+            Value::Unit => format!("[{tag}:]"),
+            val => format!("[{tag}: {}]", macro_display(val)),
+        },
+        Value::Map(map) => {
+            let entries: Vec<_> = map
+                .entries
+                .into_iter()
+                .map(|(k, v)| format!("{}: {}", macro_display(k), macro_display(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Command(pipeline) => pipeline.to_string(),
+        Value::Output(output) => output.stdout_str().trim().to_string(),
+        Value::Process(_) => "process".into(),
+        Value::Fn(f) => f.ty().to_string(),
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Int(i64),
+    /// Always finite, as operations which would give `inf` or `NaN` are errors instead.
+    Float(f64),
     Str(String),
     Bool(bool),
     List(Vec<Value>),
     Unit,
 
-    // Tuple(Vec<Value>),
+    Tuple(Vec<Value>),
     Record(HashMap<String, Value>),
-    // Variant(String, Box<Value>),
-    // Command { command: Rc<RefCell<Command>> },
+    Map(Map),
+    /// A tag, such as `ok` in `[ok: 5]`, along with its value.
+    Variant(String, Box<Value>),
+    Fn(Rc<Closure>),
+    Command(Pipeline),
+    // boxed, as it's much larger than the other variants
+    Output(Box<Output>),
+    Process(Process),
     // Internal(TODO)
 }
 
-fn extract_command(val: &Value) -> Option<(String, Vec<String>)> {
-    let Value::Record(r) = val else {
-        return None;
-    };
-    let Value::Str(program) = r.get("program")? else {
-        return None;
-    };
-    let Value::List(args) = r.get("args")? else {
+/// A function value: a closure, or a function defined with `fn` used by name.
+///
+/// A closure has a copy of the variables in scope where it was written, taken when the closure
+/// is evaluated. Assigning to one of them only changes the closure's copy, which is kept
+/// between calls.
+pub struct Closure {
+    args: Vec<Param>,
+    out: Type,
+    block: AstNode,
+    env: Env,
+}
+
+impl Closure {
+    fn ty(&self) -> Type {
+        let args = self.args.iter().map(|param| param.ty.clone()).collect();
+        Type::Fn(args, Box::new(self.out.clone()))
+    }
+}
+
+/// A function is only equal to itself, as there's no telling whether two do the same thing.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// the variables are left out, as there can be a lot of them
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure({})", self.ty())
+    }
+}
+
+/// A map which keeps its keys in the order they were first inserted, which is the order they're
+/// displayed and iterated over in.
+///
+/// Keys are compared with `==`, as values can't be hashed.
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+}
+
+impl Map {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Insert a value, replacing the value for an existing key without changing its position.
+    pub fn insert(&mut self, key: Value, value: Value) {
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let i = self.entries.iter().position(|(k, _)| k == key)?;
+        Some(self.entries.remove(i).1)
+    }
+
+    pub fn keys(&self) -> Vec<Value> {
+        self.entries.iter().map(|(k, _)| k.clone()).collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        self.entries.iter().map(|(_, v)| v.clone()).collect()
+    }
+}
+
+/// Maps are equal if they have the same entries, regardless of order.
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.entries.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+/// Split a line into `n` whitespace separated fields, where the last field is the rest of the
+/// line (as with bash's `read`). None if there are fewer than `n` fields.
+fn split_fields(line: &str, n: usize) -> Option<Vec<&str>> {
+    let mut fields = Vec::with_capacity(n);
+    let mut rest = line.trim();
+    for _ in 1..n {
+        let (field, tail) = rest.split_once(char::is_whitespace)?;
+        fields.push(field);
+        rest = tail.trim_start();
+    }
+    if rest.is_empty() {
         return None;
-    };
-    let args: Vec<String> = args
-        .iter()
-        .map(|v| match v {
-            Value::Str(a) => a.clone(),
-            _ => unreachable!(),
-        })
-        .collect();
+    }
+    fields.push(rest);
+    Some(fields)
+}
 
-    Some((program.clone(), args))
+/// The fields of an output, as described by `Type::output_record`.
+fn output_record(output: &Output) -> Value {
+    let ints = |xs: &[i64]| Value::List(xs.iter().map(|x| Value::Int(*x)).collect());
+    let bytes = |xs: &[u8]| Value::List(xs.iter().map(|x| Value::Int((*x).into())).collect());
+    Value::Record(HashMap::from_iter([
+        (String::from("status"), Value::Int(output.status())),
+        (String::from("success"), Value::Bool(output.success())),
+        (String::from("stdout"), Value::Str(output.stdout_str())),
+        (String::from("stderr"), Value::Str(output.stderr_str())),
+        (String::from("pipestatus"), ints(&output.statuses)),
+        (String::from("stdout_bytes"), bytes(&output.stdout)),
+        (String::from("stderr_bytes"), bytes(&output.stderr)),
+    ]))
 }
 
+/// Convert a record to a map, or to another record with a subset of its fields (as described
+/// by `types::record_is_subtype`).
 fn convert_record(val: Value, ty: &Type) -> Option<Value> {
     let Value::Record(r) = val else {
         return None;
     };
 
-    // let t_process: Type = Type::Record(HashMap::from_iter([
-    // (String::from("_process"), Type::Unit),
-    // (String::from("_process"), Type::Unit),
-    // (String::from("_process"), Type::Unit),
-    // ]));
-
     match ty {
-        Type::Str if r.contains_key("_command") => {
-            // TODO: convert_record(convert_record(Value::Record(r), &t_process)?, &Type::Str)
-            let Value::Str(program) = r.get("program")? else {
-                todo!();
-            };
-            let Value::List(args) = r.get("args")? else {
-                todo!();
-            };
-            let args: Vec<String> = args
-                .iter()
-                .map(|v| match v {
-                    Value::Str(a) => a.clone(),
-                    _ => unreachable!(),
-                })
-                .collect();
-
-            let mut cmd = Command::new(program);
-            cmd.args(args);
-            let output = cmd.output().unwrap();
-            let stdout = String::from_utf8(output.stdout).unwrap();
-            Some(Value::Str(stdout.trim().to_string()))
-        }
-        // Type::Str if r.contains_key("_process") => todo!(),
-        Type::Str => {
-            todo!()
-        }
-        Type::Int => todo!(),
-        Type::Bool => todo!(),
-        Type::Unit => todo!(),
-        Type::Float => todo!(),
-        Type::List(_) => todo!(),
-        Type::Map(_, _) => todo!(),
-        Type::Tuple(_) => todo!(),
-        Type::Variant(_) => todo!(),
-        Type::Record(z) => {
-            // for now, implement with markers only
-            if r.contains_key("_command") && z.contains_key("_process") {
-                todo!();
-                // if let Value::Command { command } = &out {
-                // let _ = command.borrow_mut().spawn().unwrap().wait();
-                // }
-                // return None;
+        Type::Str
+        | Type::Command
+        | Type::Output
+        | Type::Process
+        | Type::Int
+        | Type::Bool
+        | Type::Unit
+        | Type::Float
+        | Type::List(_)
+        | Type::Tuple(_)
+        | Type::Variant(_)
+        | Type::Fn(..) => None,
+        Type::Named(_) | Type::Alias(_) => unreachable!("handled by Value::convert"),
+        // fields are in order of their names, as records have no order of their own
+        Type::Map(key, value) => {
+            let mut fields: Vec<_> = r.into_iter().collect();
+            fields.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+            let mut map = Map::default();
+            for (k, v) in fields {
+                map.insert(Value::Str(k).convert(key)?, v.convert(value)?);
             }
-
+            Some(Value::Map(map))
+        }
+        Type::Record(z) => {
             let mut out = HashMap::new();
             for (key, t) in z.iter() {
-                match r.get(key) {
-                    Some(val) => match val.clone().convert(t) {
-                        Some(c_val) => {
-                            out.insert(key.clone(), c_val);
-                        }
-                        None => return None,
-                    },
-                    None => return None,
-                }
+                let c_val = r.get(key)?.clone().convert(t)?;
+                out.insert(key.clone(), c_val);
             }
             Some(Value::Record(out))
         }
@@ -339,37 +1637,129 @@ fn convert_record(val: Value, ty: &Type) -> Option<Value> {
 impl Value {
     pub fn convert(self, ty: &Type) -> Option<Value> {
         match (&self, ty) {
+            (_, Type::Alias(alias)) => self.convert(alias.def()),
+            (_, Type::Named(_)) => unreachable!("resolved by TypeEnv"),
             (&Value::Record(_), _) => convert_record(self, ty),
-            (Value::Str(s), Type::Int) => Some(Value::Int(s.trim().parse().unwrap())),
+            (Value::Process(process), Type::Output | Type::Str | Type::Int | Type::Float)
+            | (Value::Process(process), Type::List(_))
+            | (Value::Process(process), Type::Record(_) | Type::Tuple(_)) => {
+                Value::Output(Box::new(process.wait())).convert(ty)
+            }
+            (Value::Output(output), Type::Str) => {
+                Some(Value::Str(output.stdout_str().trim().to_string()))
+            }
+            // numbers are read from the first field, as in `let load: float = [cat /proc/loadavg]`
+            (Value::Output(output), Type::Int | Type::Float) => {
+                let stdout = output.stdout_str();
+                let field = stdout.split_whitespace().next().unwrap_or_default();
+                Value::Str(field.to_string()).convert(ty)
+            }
+            // outputs are converted to other types by way of their stdout
+            (Value::Output(_), Type::List(_) | Type::Tuple(_)) => {
+                self.convert(&Type::Str)?.convert(ty)
+            }
+            (Value::Output(output), Type::Record(_)) => output_record(output).convert(ty),
+            (Value::Str(s), Type::Int) => s.trim().parse().ok().map(Value::Int),
+            // `inf` and `NaN` aren't allowed, as they aren't numbers a script can work with
+            (Value::Str(s), Type::Float) => {
+                let x: f64 = s.trim().parse().ok()?;
+                x.is_finite().then_some(Value::Float(x))
+            }
+            (Value::Int(x), Type::Float) => Some(Value::Float(*x as f64)),
+            (Value::Map(map), Type::Map(key, value)) => {
+                let mut out = Map::default();
+                for (k, v) in map.entries.iter() {
+                    out.insert(k.clone().convert(key)?, v.clone().convert(value)?);
+                }
+                Some(Value::Map(out))
+            }
+            // maps with `str` keys can be converted to records, which need every field
+            (Value::Map(map), Type::Record(fields)) => {
+                let mut out = HashMap::new();
+                for (name, t) in fields {
+                    let v = map.get(&Value::Str(name.clone()))?;
+                    out.insert(name.clone(), v.clone().convert(t)?);
+                }
+                Some(Value::Record(out))
+            }
             (Value::List(xs), Type::List(t)) => {
-                let ys: Vec<_> = xs.iter().map(|x| x.clone().convert(t).unwrap()).collect();
+                let ys = xs
+                    .iter()
+                    .map(|x| x.clone().convert(t))
+                    .collect::<Option<Vec<_>>>()?;
                 Some(Value::List(ys))
             }
+            (Value::Str(s), Type::Tuple(ts)) => {
+                let fields = split_fields(s, ts.len())?;
+                let xs = fields
+                    .into_iter()
+                    .zip(ts)
+                    .map(|(field, t)| Value::Str(field.to_string()).convert(t))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Value::Tuple(xs))
+            }
+            (Value::Variant(tag, val), Type::Variant(tags)) => {
+                let val = val.clone().convert(tags.get(tag)?)?;
+                Some(Value::Variant(tag.clone(), Box::new(val)))
+            }
+            (Value::Tuple(xs), Type::Tuple(ts)) if xs.len() == ts.len() => {
+                let ys = xs
+                    .iter()
+                    .zip(ts)
+                    .map(|(x, t)| x.clone().convert(t))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Value::Tuple(ys))
+            }
             (Value::Str(s), Type::List(t)) => Some(Value::List(
-                s.trim()
-                    .split_whitespace()
-                    .map(|x| Value::Str(String::from(x)).convert(t).unwrap())
-                    .collect(),
+                s.split_whitespace()
+                    .map(|x| Value::Str(String::from(x)).convert(t))
+                    .collect::<Option<Vec<_>>>()?,
             )),
-            (Value::List(xs), Type::Str) => {
-                let ys: Vec<_> = xs
+            (Value::List(xs) | Value::Tuple(xs), Type::Str) => {
+                let ys = xs
                     .iter()
-                    .map(|x| {
-                        let Value::Str(v) = x.clone().convert(&Type::Str).unwrap() else {
-                            todo!();
-                        };
-                        v
+                    .map(|x| match x.clone().convert(&Type::Str)? {
+                        Value::Str(v) => Some(v),
+                        _ => None,
                     })
-                    .collect();
+                    .collect::<Option<Vec<_>>>()?;
 
                 Some(Value::Str(ys.join(" ")))
             }
             (Value::Int(x), Type::Str) => Some(Value::Str(x.to_string())),
+            (Value::Float(x), Type::Str) => Some(Value::Str(float_string(*x))),
+            (Value::Bool(b), Type::Str) => Some(Value::Str(b.to_string())),
             // everything can be converted to itself
             (Value::Int(_), Type::Int) => Some(self),
+            (Value::Float(_), Type::Float) => Some(self),
             (Value::Str(_), Type::Str) => Some(self),
             (Value::Bool(_), Type::Bool) => Some(self),
+            (Value::Unit, Type::Unit) => Some(self),
+            (Value::Command(_), Type::Command) => Some(self),
+            (Value::Output(_), Type::Output) => Some(self),
+            (Value::Process(_), Type::Process) => Some(self),
+            (Value::Fn(_), Type::Fn(..)) => Some(self),
             _ => None,
         }
     }
+
+    /// Short description of a value for use in error messages, e.g. `int 5`.
+    pub fn describe(&self) -> String {
+        match self {
+            Value::Int(x) => format!("int `{x}`"),
+            Value::Float(x) => format!("float `{}`", float_string(*x)),
+            Value::Str(s) => format!("str {s:?}"),
+            Value::Bool(b) => format!("bool `{b}`"),
+            Value::List(_) => format!("list `{}`", macro_display(self.clone())),
+            Value::Tuple(_) => format!("tuple `{}`", macro_display(self.clone())),
+            Value::Unit => String::from("unit"),
+            Value::Command(pipeline) => format!("command `{pipeline}`"),
+            Value::Output(output) => format!("output (status {})", output.status()),
+            Value::Process(_) => String::from("process"),
+            Value::Record(_) => format!("record `{}`", macro_display(self.clone())),
+            Value::Map(_) => format!("map `{}`", macro_display(self.clone())),
+            Value::Variant(..) => format!("variant `{}`", macro_display(self.clone())),
+            Value::Fn(f) => format!("function `{}`", f.ty()),
+        }
+    }
 }
@@ -1,20 +1,29 @@
 pub mod ast;
+pub mod bash;
+pub mod command;
+pub mod diagnostic;
+pub mod exhaustive;
 pub mod interpreter;
 pub mod parse;
+pub mod repl;
+pub mod typeck;
 pub mod types;
 
-use std::error::Error;
-
-use clap::Parser;
+use ast::AstNode;
+use clap::{Parser, Subcommand, ValueEnum};
 use parse::{KleyParser, Rule};
 use pest::Parser as _;
 
 /// Kley language implementation
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Kley script to process
-    file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Kley script to process, or start a REPL if omitted
+    file: Option<String>,
 
     #[arg(long)]
     debug_peg: bool,
@@ -23,35 +32,126 @@ struct Args {
     debug_ast: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Type check a script without running it
+    Check {
+        /// Kley script to check
+        file: String,
+    },
+    /// Transpile a script so it can run without kley installed
+    Build {
+        /// Language to transpile to
+        #[arg(long, value_enum, default_value_t = Target::Bash)]
+        target: Target,
+
+        /// Write the output to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Kley script to transpile
+        file: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Target {
+    Bash,
+}
+
 fn main() {
     let args = Args::parse();
 
-    if let Err(e) = run_interpreter(&args) {
-        eprintln!("{e}");
-        return;
+    let result = match &args.command {
+        Some(Command::Check { file }) => run_check(file),
+        Some(Command::Build {
+            target: Target::Bash,
+            output,
+            file,
+        }) => run_build(file, output.as_deref()),
+        None if args.file.is_none() => repl::run(),
+        None => run_interpreter(&args),
+    };
+    if let Err(e) = result {
+        eprint!("{e}");
+        std::process::exit(1);
     };
 }
 
-fn run_interpreter(args: &Args) -> Result<(), Box<dyn Error>> {
-    let code = std::fs::read_to_string(args.file.clone())?;
-    let mut pairs = KleyParser::parse(Rule::program, &code)?;
+fn read_source(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("error: could not read {path}: {e}\n"))
+}
+
+fn parse_error(e: pest::error::Error<Rule>, path: &str) -> String {
+    format!("error: failed to parse\n{}\n", e.with_path(path))
+}
+
+/// Run the type checker, rendering every error found.
+fn typecheck(ast: &AstNode, code: &str, path: &str) -> Result<(), String> {
+    typeck::check(ast).map_err(|errors| {
+        let rendered: Vec<String> = errors.iter().map(|e| e.render(code, path)).collect();
+        format!(
+            "{}\nerror: found {} type error(s) in {path}\n",
+            rendered.join("\n"),
+            errors.len()
+        )
+    })
+}
+
+fn run_check(path: &str) -> Result<(), String> {
+    let code = read_source(path)?;
+    let pairs = KleyParser::parse(Rule::program, &code).map_err(|e| parse_error(e, path))?;
+    let ast = parse::build_ast(pairs).map_err(|e| parse_error(e, path))?;
+    typecheck(&ast, &code, path)
+}
+
+fn run_build(path: &str, output: Option<&str>) -> Result<(), String> {
+    let code = read_source(path)?;
+    let pairs = KleyParser::parse(Rule::program, &code).map_err(|e| parse_error(e, path))?;
+    let ast = parse::build_ast(pairs).map_err(|e| parse_error(e, path))?;
+    typecheck(&ast, &code, path)?;
+    let script = bash::transpile(&ast, path).map_err(|e| e.render(&code, path))?;
+
+    let Some(output) = output else {
+        print!("{script}");
+        return Ok(());
+    };
+    std::fs::write(output, script)
+        .map_err(|e| format!("error: could not write {output}: {e}\n"))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::Permissions::from_mode(0o755);
+        std::fs::set_permissions(output, permissions)
+            .map_err(|e| format!("error: could not make {output} executable: {e}\n"))?;
+    }
+    Ok(())
+}
+
+/// Runs the script given in `args`, returning a fully rendered error report on failure.
+fn run_interpreter(args: &Args) -> Result<(), String> {
+    let path = args
+        .file
+        .as_deref()
+        .expect("REPL is used when no file is given");
+    let code = read_source(path)?;
+    let mut pairs = KleyParser::parse(Rule::program, &code).map_err(|e| parse_error(e, path))?;
 
     if args.debug_peg {
         parse::display_pairs(&mut pairs, 0);
         return Ok(());
     }
-    let ast = parse::build_ast(pairs)?;
+    let ast = parse::build_ast(pairs).map_err(|e| parse_error(e, path))?;
 
     if args.debug_ast {
         println!("{:#?}", ast);
         return Ok(());
     }
 
-    let _ = interpreter::eval(&ast);
+    typecheck(&ast, &code, path)?;
+    interpreter::eval(&ast).map_err(|e| e.diagnostic().render(&code, path))?;
 
     Ok(())
 }
 
 // TODO: JIT compilation
-
-// TODO: bash transpiler
@@ -1,19 +1,46 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::LazyLock};
 
 use pest::{
-    error::Error,
+    error::{Error, ErrorVariant},
     iterators::{Pair, Pairs},
-    Parser,
+    pratt_parser::{Assoc, Op, PrattParser},
 };
 use pest_derive::Parser;
 
-use crate::{ast::*, types::Type, Args};
+use crate::{ast::*, types::Type};
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct KleyParser;
 
+/// Operator precedence for expressions, from lowest to highest binding power.
+static PRATT_PARSER: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    PrattParser::new()
+        .op(Op::infix(Rule::pipe, Assoc::Left))
+        .op(Op::infix(Rule::or, Assoc::Left))
+        .op(Op::infix(Rule::and, Assoc::Left))
+        .op(Op::infix(Rule::eq, Assoc::Left)
+            | Op::infix(Rule::ne, Assoc::Left)
+            | Op::infix(Rule::lt, Assoc::Left)
+            | Op::infix(Rule::gt, Assoc::Left)
+            | Op::infix(Rule::le, Assoc::Left)
+            | Op::infix(Rule::ge, Assoc::Left))
+        .op(Op::infix(Rule::range, Assoc::Left))
+        .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
+        .op(Op::infix(Rule::mul, Assoc::Left)
+            | Op::infix(Rule::div, Assoc::Left)
+            | Op::infix(Rule::rem, Assoc::Left))
+        .op(Op::prefix(Rule::neg) | Op::prefix(Rule::not))
+        .op(Op::postfix(Rule::propagate)
+            | Op::postfix(Rule::index)
+            | Op::postfix(Rule::tuple_index)
+            | Op::postfix(Rule::field)
+            | Op::postfix(Rule::apply))
+});
+
 pub fn build_ast(pairs: Pairs<Rule>) -> Result<AstNode, Error<Rule>> {
+    check_fields(&pairs)?;
+    check_literals(&pairs)?;
     let mut ast = vec![];
 
     for pair in pairs {
@@ -25,7 +52,130 @@ pub fn build_ast(pairs: Pairs<Rule>) -> Result<AstNode, Error<Rule>> {
         }
     }
 
-    Ok(AstNode::Block(ast))
+    let span = match (ast.first(), ast.last()) {
+        (Some(first), Some(last)) => first.span.join(last.span),
+        _ => Span::default(),
+    };
+    // everything at the top level is a statement, so the program itself has no value
+    ast.push(AstNode {
+        kind: AstKind::Unit,
+        span,
+    });
+
+    Ok(AstNode {
+        kind: AstKind::Block(ast),
+        span,
+    })
+}
+
+/// Build the AST for a `repl_input`, as a block whose value is the trailing expression (if any).
+pub fn build_repl_ast(pairs: Pairs<Rule>, span: Span) -> Result<AstNode, Error<Rule>> {
+    check_fields(&pairs)?;
+    check_literals(&pairs)?;
+    let pairs = pairs.filter(|pair| pair.as_rule() != Rule::EOI);
+    Ok(AstNode {
+        kind: parse_block(pairs, span),
+        span,
+    })
+}
+
+/// The largest precision allowed in an interpolation like `{x:.2}`, as formatting panics
+/// beyond it.
+const MAX_PRECISION: usize = u16::MAX as usize;
+
+/// Report a number literal (or an int pattern) which doesn't fit in an `int`, or is too large
+/// for a `float`, a tuple index which doesn't fit in a `usize`, and a precision larger than
+/// `MAX_PRECISION`.
+fn check_literals(pairs: &Pairs<Rule>) -> Result<(), Error<Rule>> {
+    for pair in pairs.clone().flatten() {
+        let literal = pair.as_str().trim();
+        let (in_range, message) = match pair.as_rule() {
+            Rule::number if literal.contains(['.', 'e', 'E']) => (
+                literal.parse::<f64>().is_ok_and(f64::is_finite),
+                format!("number literal `{literal}` is out of range"),
+            ),
+            Rule::number | Rule::int_pattern => (
+                literal.parse::<i64>().is_ok(),
+                format!("number literal `{literal}` is out of range"),
+            ),
+            // `.` followed by digits
+            Rule::tuple_index => (
+                literal[1..].parse::<usize>().is_ok(),
+                format!("tuple index `{}` is out of range", &literal[1..]),
+            ),
+            // `:.` followed by digits
+            Rule::precision => (
+                literal[2..]
+                    .parse::<usize>()
+                    .is_ok_and(|precision| precision <= MAX_PRECISION),
+                format!(
+                    "precision `{}` is out of range, the most is {MAX_PRECISION}",
+                    &literal[2..]
+                ),
+            ),
+            _ => continue,
+        };
+        if !in_range {
+            return Err(Error::new_from_span(
+                ErrorVariant::CustomError { message },
+                pair.as_span(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Report a field of a record type (or a tag of a variant type) which is given more than once.
+fn check_fields(pairs: &Pairs<Rule>) -> Result<(), Error<Rule>> {
+    for pair in pairs.clone().flatten() {
+        let (names, what): (Vec<_>, _) = match pair.as_rule() {
+            Rule::t_record => (pair.into_inner().collect(), "field"),
+            Rule::t_variant => (pair.into_inner().collect(), "tag"),
+            Rule::enum_decl => {
+                let tags = pair.into_inner().skip(1);
+                (
+                    tags.filter_map(|tag| tag.into_inner().next()).collect(),
+                    "tag",
+                )
+            }
+            _ => continue,
+        };
+        let names: Vec<_> = names
+            .into_iter()
+            .filter(|p| p.as_rule() == Rule::ident)
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].iter().any(|n| n.as_str() == name.as_str()) {
+                let message = format!("duplicate {what} `{}`", name.as_str());
+                return Err(Error::new_from_span(
+                    ErrorVariant::CustomError { message },
+                    name.as_span(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The last node of a block is always its value, which is unit if the block ended with a
+/// statement instead of an expression.
+fn parse_block<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>, span: Span) -> AstKind {
+    let mut out = Vec::new();
+    let mut has_value = false;
+    for pair in pairs {
+        has_value = matches!(
+            pair.as_rule(),
+            Rule::expression | Rule::ifthenelse | Rule::try_catch | Rule::match_expr
+        );
+        out.push(parse_term(pair));
+    }
+    if !has_value {
+        out.push(AstNode {
+            kind: AstKind::Unit,
+            span,
+        });
+    }
+    AstKind::Block(out)
 }
 
 pub fn display_pairs(pairs: &mut Pairs<Rule>, tab: usize) {
@@ -48,26 +198,207 @@ fn get_ast(pairs: &mut Pairs<Rule>) -> Box<AstNode> {
     Box::new(parse_term(pairs.next().unwrap()))
 }
 
-fn parse_term(pair: Pair<Rule>) -> AstNode {
+fn parse_expression(pairs: Pairs<Rule>) -> AstNode {
+    PRATT_PARSER
+        .map_primary(parse_term)
+        .map_prefix(|op, rhs| {
+            let span = Span::from(op.as_span()).join(rhs.span);
+            let verb = match op.as_rule() {
+                Rule::neg => PrefixVerb::Negate,
+                Rule::not => PrefixVerb::Not,
+                rule => unreachable!("unexpected prefix verb: {:?}", rule),
+            };
+            let kind = AstKind::PrefixExpr {
+                verb,
+                expr: Box::new(rhs),
+            };
+            AstNode { kind, span }
+        })
+        .map_postfix(|lhs, op| {
+            let span = lhs.span.join(Span::from(op.as_span()));
+            let kind = match op.as_rule() {
+                Rule::propagate => AstKind::Propagate(Box::new(lhs)),
+                Rule::tuple_index => AstKind::TupleIndex {
+                    expr: Box::new(lhs),
+                    // `tuple_index` is always `.` followed by digits, checked by check_literals
+                    index: op.as_str()[1..].parse().expect("checked by check_literals"),
+                },
+                Rule::field => AstKind::Field {
+                    expr: Box::new(lhs),
+                    name: op.as_str()[1..].to_string(),
+                },
+                Rule::apply => AstKind::Apply {
+                    expr: Box::new(lhs),
+                    args: op.into_inner().map(parse_term).collect(),
+                },
+                Rule::index => {
+                    let index = parse_term(op.into_inner().next().unwrap());
+                    match index.kind {
+                        AstKind::InfixExpr {
+                            verb: InfixVerb::Range,
+                            lhs: start,
+                            rhs: end,
+                        } => AstKind::Slice {
+                            expr: Box::new(lhs),
+                            start,
+                            end,
+                        },
+                        _ => AstKind::Index {
+                            expr: Box::new(lhs),
+                            index: Box::new(index),
+                        },
+                    }
+                }
+                rule => unreachable!("unexpected postfix verb: {:?}", rule),
+            };
+            AstNode { kind, span }
+        })
+        .map_infix(|lhs, op, rhs| {
+            let span = lhs.span.join(rhs.span);
+            let verb = match op.as_rule() {
+                Rule::add => InfixVerb::Plus,
+                Rule::sub => InfixVerb::Minus,
+                Rule::mul => InfixVerb::Times,
+                Rule::div => InfixVerb::Divide,
+                Rule::rem => InfixVerb::Modulo,
+                Rule::eq => InfixVerb::Equal,
+                Rule::ne => InfixVerb::NotEqual,
+                Rule::lt => InfixVerb::Less,
+                Rule::gt => InfixVerb::Greater,
+                Rule::le => InfixVerb::LessEqual,
+                Rule::ge => InfixVerb::GreaterEqual,
+                Rule::and => InfixVerb::And,
+                Rule::or => InfixVerb::Or,
+                Rule::pipe => InfixVerb::Pipe,
+                Rule::range => InfixVerb::Range,
+                _ => unreachable!("unexpected infix verb: {}", op.as_str()),
+            };
+            let kind = AstKind::InfixExpr {
+                verb,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+            AstNode { kind, span }
+        })
+        .parse(pairs)
+}
+
+fn parse_pattern(pair: Pair<Rule>) -> Pattern {
     match pair.as_rule() {
+        Rule::ident => Pattern::Ident(pair.as_str().to_string()),
+        Rule::wildcard => Pattern::Wildcard,
+        // out of range patterns are reported by check_literals
+        Rule::int_pattern => Pattern::Int(pair.as_str().parse().unwrap()),
+        Rule::str_pattern => Pattern::Str(pair.into_inner().as_str().to_string()),
+        Rule::boolean => Pattern::Bool(pair.as_str() == "true"),
+        Rule::tuple_pattern => Pattern::Tuple(pair.into_inner().map(parse_pattern).collect()),
+        Rule::record_pattern => {
+            let mut fields = Vec::new();
+            let mut inner = pair.into_inner();
+            while inner.peek().is_some() {
+                let name = get_string(&mut inner);
+                fields.push((name, parse_pattern(inner.next().unwrap())));
+            }
+            Pattern::Record(fields)
+        }
+        // `[none:]` matches the same as `[none: _]`
+        Rule::variant_pattern => {
+            let mut inner = pair.into_inner();
+            let tag = get_string(&mut inner);
+            let pattern = inner.next().map_or(Pattern::Wildcard, parse_pattern);
+            Pattern::Variant {
+                tag,
+                pattern: Box::new(pattern),
+            }
+        }
+        rule => unreachable!("unexpected pattern: {:?}", rule),
+    }
+}
+
+fn parse_match_arm(pair: Pair<Rule>) -> MatchArm {
+    let span = Span::from(pair.as_span());
+    let mut inner = pair.into_inner();
+    let pattern = parse_pattern(inner.next().unwrap());
+    let guard = match inner.peek().map(|p| p.as_rule()) {
+        Some(Rule::guard) => Some(*get_ast(&mut inner.next().unwrap().into_inner())),
+        _ => None,
+    };
+    MatchArm {
+        pattern,
+        guard,
+        body: *get_ast(&mut inner),
+        span,
+    }
+}
+
+/// An argument of a function defined with `fn`, which can have a default.
+fn parse_param(pair: Pair<Rule>) -> Param {
+    let mut inner = pair.into_inner();
+    Param {
+        pattern: parse_pattern(inner.next().unwrap()),
+        ty: Type::parse(inner.next().unwrap()),
+        default: inner.next().map(parse_term),
+    }
+}
+
+/// Arguments of a closure, each with its type.
+fn parse_closure_args(pair: Pair<Rule>) -> Vec<(Pattern, Type)> {
+    let mut args = Vec::new();
+    let mut inner = pair.into_inner();
+    while inner.peek().is_some() {
+        let pattern = parse_pattern(inner.next().unwrap());
+        let ty = Type::parse(inner.next().unwrap());
+        args.push((pattern, ty));
+    }
+    args
+}
+
+fn parse_command_token(pair: Pair<Rule>) -> CommandToken {
+    let span = Span::from(pair.as_span());
+    let mut parts: Vec<AstNode> = Vec::new();
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::command_text | Rule::quote_string | Rule::raw_string | Rule::interpolation => {
+                parts.push(parse_term(part));
+            }
+            _ => unimplemented!(),
+        }
+    }
+    CommandToken { parts, span }
+}
+
+fn parse_redirect(pair: Pair<Rule>) -> Redirection {
+    let span = Span::from(pair.as_span());
+    let mut inner = pair.into_inner();
+    let op = inner.next().unwrap();
+    Redirection {
+        op: RedirectOp::from_symbol(op.as_str()).expect("grammar only allows valid operators"),
+        target: inner.next().map(parse_command_token),
+        span,
+    }
+}
+
+fn parse_term(pair: Pair<Rule>) -> AstNode {
+    let span = Span::from(pair.as_span());
+    let kind = match pair.as_rule() {
         Rule::assign => {
             let mut inner = pair.into_inner();
             // println!("assign: {}", inner);
             let ident = get_string(&mut inner);
             let expr = get_ast(&mut inner);
-            AstNode::Assign { ident, expr }
+            AstKind::Assign { ident, expr }
         }
         Rule::binding => {
             let mut inner = pair.into_inner();
             // println!("binding: {}", inner);
-            let ident = get_string(&mut inner);
+            let pattern = parse_pattern(inner.next().unwrap());
             // let ty = Type::parse(&get_string(&mut inner));
             let ty = Type::parse(inner.next().unwrap());
             let expr = get_ast(&mut inner);
-            AstNode::Binding { ident, ty, expr }
+            AstKind::Binding { pattern, ty, expr }
         }
-        Rule::expression => todo!(),
-        Rule::expr => parse_term(pair.into_inner().next().unwrap()),
+        Rule::expression => return parse_expression(pair.into_inner()),
+        Rule::expr => return parse_term(pair.into_inner().next().unwrap()),
         Rule::atom => todo!(),
         // Rule::block => AstNode::Block(pair.into_inner().map(parse_term).collect()),
         // Rule::command_text => todo!(),
@@ -85,113 +416,230 @@ fn parse_term(pair: Pair<Rule>) -> AstNode {
         // },
         Rule::number => {
             let istr = pair.as_str().trim();
-            let num = istr.parse().unwrap();
-            AstNode::Integer(num)
+            // out of range literals are reported by check_literals
+            match istr.contains(['.', 'e', 'E']) {
+                true => AstKind::Float(istr.parse().unwrap()),
+                false => AstKind::Integer(istr.parse().unwrap()),
+            }
         }
         Rule::boolean => match pair.as_str().trim() {
-            "true" => AstNode::Boolean(true),
-            "false" => AstNode::Boolean(false),
+            "true" => AstKind::Boolean(true),
+            "false" => AstKind::Boolean(false),
             _ => todo!(),
         },
-        Rule::ident => AstNode::Ident(String::from(pair.as_str())),
-        Rule::infix => todo!(),
-        Rule::infix_expr => {
-            let mut inner = pair.into_inner();
-            let lhs = get_ast(&mut inner);
-            let vstr = get_string(&mut inner);
-            let rhs = get_ast(&mut inner);
-
-            AstNode::InfixExpr {
-                verb: match vstr.as_str() {
-                    "+" => InfixVerb::Plus,
-                    "-" => InfixVerb::Minus,
-                    "*" => InfixVerb::Times,
-                    "/" => InfixVerb::Divide,
-                    _ => panic!("unexpected infix verb: {}", vstr),
-                },
-                lhs,
-                rhs,
-            }
+        Rule::ident => AstKind::Ident(String::from(pair.as_str())),
+        // operators are handled by parse_expression
+        Rule::prefix | Rule::neg | Rule::not => unreachable!(),
+        Rule::postfix
+        | Rule::propagate
+        | Rule::index
+        | Rule::tuple_index
+        | Rule::field
+        | Rule::apply => {
+            unreachable!()
         }
+        Rule::infix
+        | Rule::add
+        | Rule::sub
+        | Rule::mul
+        | Rule::div
+        | Rule::rem
+        | Rule::ge
+        | Rule::le
+        | Rule::gt
+        | Rule::lt
+        | Rule::eq
+        | Rule::ne
+        | Rule::and
+        | Rule::or
+        | Rule::pipe
+        | Rule::range => unreachable!(),
+        Rule::list_value => AstKind::ListValue(pair.into_inner().map(parse_term).collect()),
+        Rule::tuple_value => AstKind::TupleValue(pair.into_inner().map(parse_term).collect()),
         Rule::command => {
+            // each stage is piped into the next, the same as the `|` operator
+            let stages = pair.into_inner().map(parse_term);
+            let mut out = stages
+                .reduce(|lhs, rhs| AstNode {
+                    span: lhs.span.join(rhs.span),
+                    kind: AstKind::InfixExpr {
+                        verb: InfixVerb::Pipe,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                })
+                .expect("commands have at least one stage");
+            // include the brackets
+            out.span = span;
+            return out;
+        }
+        Rule::command_stage => {
             let mut tokens: Vec<CommandToken> = Vec::new();
-            for command_token in pair.into_inner() {
-                assert!(command_token.as_rule() == Rule::command_token);
-
-                let mut token: Vec<AstNode> = Vec::new();
-                for part in command_token.into_inner() {
-                    match part.as_rule() {
-                        Rule::command_text
-                        | Rule::quote_string
-                        | Rule::raw_string
-                        | Rule::block_small => {
-                            token.push(parse_term(part));
-                        }
-                        _ => unimplemented!(),
-                    }
+            let mut redirects: Vec<Redirection> = Vec::new();
+            for inner in pair.into_inner() {
+                match inner.as_rule() {
+                    Rule::command_token => tokens.push(parse_command_token(inner)),
+                    Rule::redirect => redirects.push(parse_redirect(inner)),
+                    _ => unreachable!(),
                 }
-                tokens.push(CommandToken(token));
             }
 
-            AstNode::Command(tokens)
+            AstKind::Command { tokens, redirects }
         }
-        Rule::command_text => AstNode::StringLiteral(pair.as_str().to_string()),
+        Rule::command_text => AstKind::StringLiteral(pair.as_str().to_string()),
         Rule::quote_string => {
             let mut out = Vec::new();
             for part in pair.into_inner() {
                 match part.as_rule() {
-                    Rule::quote_string_text | Rule::block_small => {
+                    Rule::quote_string_text | Rule::interpolation => {
                         out.push(parse_term(part));
                     }
                     _ => unreachable!(),
                 }
             }
-            AstNode::QuoteString(out)
+            AstKind::QuoteString(out)
         }
-        Rule::raw_string => parse_term(pair.into_inner().next().unwrap()),
+        Rule::raw_string => return parse_term(pair.into_inner().next().unwrap()),
         Rule::quote_string_text | Rule::raw_string_text => {
-            AstNode::StringLiteral(pair.as_str().to_string())
+            AstKind::StringLiteral(pair.as_str().to_string())
         }
         Rule::r#type => todo!(),
-        Rule::block_small => parse_term(pair.into_inner().next().unwrap()),
-        Rule::block_large => {
-            let mut out = Vec::new();
-            for pair in pair.into_inner() {
-                out.push(parse_term(pair));
+        Rule::block_small => return parse_term(pair.into_inner().next().unwrap()),
+        Rule::interpolation => {
+            let mut inner = pair.into_inner();
+            let expr = get_ast(&mut inner);
+            match inner.next() {
+                Some(precision) => AstKind::Format {
+                    expr,
+                    // `precision` is always `:.` followed by digits, checked by check_literals
+                    precision: precision.as_str()[2..]
+                        .parse()
+                        .expect("checked by check_literals"),
+                },
+                None => return *expr,
             }
-            AstNode::Block(out)
         }
-        Rule::command_token => unreachable!(), // handled by Rule::command
+        Rule::precision => unreachable!(),
+        Rule::block_large => parse_block(pair.into_inner(), span),
+        Rule::command_token => unreachable!(), // handled by Rule::command_stage
+        Rule::redirect | Rule::redirect_op | Rule::stderr_to_stdout => unreachable!(),
         Rule::stmt => unreachable!(),
+        Rule::expr_stmt => return parse_term(pair.into_inner().next().unwrap()),
         Rule::COMMENT => unreachable!(),
         Rule::EOI => unreachable!(),
         Rule::WHITESPACE => unreachable!(),
         Rule::newline => unreachable!(),
         Rule::program => unreachable!(),
+        Rule::repl_input => unreachable!(),
         Rule::call => {
             let mut inner = pair.into_inner();
             let name = get_string(&mut inner);
             let mut args = Vec::new();
-            while inner.peek().is_some() {
-                args.push(parse_term(inner.next().unwrap()));
+            let mut named = Vec::new();
+            for arg in inner {
+                match arg.as_rule() {
+                    Rule::named_arg => {
+                        let mut inner = arg.into_inner();
+                        named.push((get_string(&mut inner), *get_ast(&mut inner)));
+                    }
+                    _ => args.push(parse_term(arg)),
+                }
+            }
+            AstKind::Call { name, args, named }
+        }
+        Rule::call_args | Rule::positional_args | Rule::named_arg => unreachable!(),
+        Rule::while_loop => {
+            let mut inner = pair.into_inner();
+            let cond = get_ast(&mut inner);
+            let block = get_ast(&mut inner);
+            AstKind::While { cond, block }
+        }
+        Rule::for_loop => {
+            let mut inner = pair.into_inner();
+            let pattern = parse_pattern(inner.next().unwrap());
+            let ty = Type::parse(inner.next().unwrap());
+            let iter = get_ast(&mut inner);
+            let block = get_ast(&mut inner);
+            AstKind::For {
+                pattern,
+                ty,
+                iter,
+                block,
+            }
+        }
+        Rule::break_stmt => AstKind::Break,
+        Rule::continue_stmt => AstKind::Continue,
+        Rule::return_stmt => {
+            let mut inner = pair.into_inner();
+            inner.next(); // the keyword
+            let expr = match inner.peek() {
+                Some(_) => get_ast(&mut inner),
+                None => Box::new(AstNode {
+                    kind: AstKind::Unit,
+                    span,
+                }),
+            };
+            AstKind::Return(expr)
+        }
+        Rule::return_keyword => unreachable!(),
+        Rule::try_catch => {
+            let mut inner = pair.into_inner();
+            let block = get_ast(&mut inner);
+            let ident = get_string(&mut inner);
+            let catch = get_ast(&mut inner);
+            AstKind::TryCatch {
+                block,
+                ident,
+                catch,
             }
-            AstNode::Call { name, args }
         }
-        Rule::call_args => todo!(),
         Rule::ifthenelse => {
             let mut inner = pair.into_inner();
             let cond = get_ast(&mut inner);
             let t_block = get_ast(&mut inner);
-            if inner.peek().is_none() {
-                todo!("omitted else blocks not implemented yet");
-            }
-            let f_block = get_ast(&mut inner);
-            AstNode::IfThenElse {
+            // an omitted else is left as a bare unit (rather than a block) so the type checker
+            // can tell it apart, with the span of the whole `if` for errors
+            let f_block = match inner.peek() {
+                Some(_) => get_ast(&mut inner),
+                None => Box::new(AstNode {
+                    kind: AstKind::Unit,
+                    span,
+                }),
+            };
+            AstKind::IfThenElse {
                 cond,
                 t_block,
                 f_block,
             }
         }
+        Rule::map_value => {
+            let mut out = Vec::new();
+            let mut inner = pair.into_inner();
+            while inner.peek().is_some() {
+                let key = get_ast(&mut inner);
+                let value = get_ast(&mut inner);
+                out.push((*key, *value));
+            }
+            AstKind::MapValue(out)
+        }
+        Rule::variant_value => {
+            let mut inner = pair.into_inner();
+            let tag = get_string(&mut inner);
+            let expr = match inner.peek() {
+                Some(_) => get_ast(&mut inner),
+                None => Box::new(AstNode {
+                    kind: AstKind::Unit,
+                    span,
+                }),
+            };
+            AstKind::VariantValue { tag, expr }
+        }
+        Rule::match_expr => {
+            let mut inner = pair.into_inner();
+            let expr = get_ast(&mut inner);
+            let arms = inner.map(parse_match_arm).collect();
+            AstKind::Match { expr, arms }
+        }
         Rule::record_value => {
             let mut out = HashMap::new();
             let mut inner = pair.into_inner();
@@ -200,38 +648,90 @@ fn parse_term(pair: Pair<Rule>) -> AstNode {
                 let ast = get_ast(&mut inner);
                 out.insert(ident, *ast);
             }
-            AstNode::RecordValue(out)
+            AstKind::RecordValue(out)
+        }
+        Rule::record_update => {
+            let mut inner = pair.into_inner();
+            let base = get_ast(&mut inner);
+            let mut fields = HashMap::new();
+            while inner.peek().is_some() {
+                let ident = get_string(&mut inner);
+                let ast = get_ast(&mut inner);
+                fields.insert(ident, *ast);
+            }
+            AstKind::RecordUpdate { base, fields }
         }
         Rule::t_str => todo!(),
         Rule::t_int => todo!(),
         Rule::t_bool => todo!(),
         Rule::t_unit => todo!(),
         Rule::t_float => todo!(),
+        Rule::t_command => todo!(),
+        Rule::t_output => todo!(),
+        Rule::t_process => todo!(),
         Rule::t_list => todo!(),
         Rule::t_map => todo!(),
         Rule::t_tuple => todo!(),
         Rule::t_variant => todo!(),
         Rule::t_record => todo!(),
+        Rule::t_fn => todo!(),
         Rule::t_ident => todo!(),
         Rule::function_def => {
             let mut inner = pair.into_inner();
             let name = get_string(&mut inner);
-            let mut args = Vec::new();
-            let mut function_args = inner.next().unwrap().into_inner();
-            while function_args.peek().is_some() {
-                let ident = get_string(&mut function_args);
-                let ty = Type::parse(function_args.next().unwrap());
-                args.push((ident, ty));
-            }
+            let args = inner
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(parse_param)
+                .collect();
             let out = Type::parse(inner.next().unwrap());
             let block = get_ast(&mut inner);
-            AstNode::Function {
+            AstKind::Function {
                 name,
                 args,
                 out,
                 block,
             }
         }
-        Rule::function_args => todo!(),
-    }
+        Rule::closure => {
+            let mut inner = pair.into_inner();
+            let args = parse_closure_args(inner.next().unwrap());
+            let out = Type::parse(inner.next().unwrap());
+            let block = get_ast(&mut inner);
+            AstKind::Closure { args, out, block }
+        }
+        Rule::function_args | Rule::param | Rule::closure_args => unreachable!(),
+        Rule::type_decl => {
+            let mut inner = pair.into_inner();
+            let name = get_string(&mut inner);
+            let ty = Type::parse(inner.next().unwrap());
+            AstKind::TypeDecl { name, ty }
+        }
+        Rule::enum_decl => {
+            let mut inner = pair.into_inner();
+            let name = get_string(&mut inner);
+            let mut tags = HashMap::new();
+            for tag in inner {
+                let mut tag = tag.into_inner();
+                let tag_name = get_string(&mut tag);
+                let ty = tag.next().map_or(Type::Unit, Type::parse);
+                tags.insert(tag_name, ty);
+            }
+            AstKind::TypeDecl {
+                name,
+                ty: Type::Variant(tags),
+            }
+        }
+        Rule::enum_tag => unreachable!("handled by Rule::enum_decl"),
+        Rule::pattern
+        | Rule::wildcard
+        | Rule::int_pattern
+        | Rule::str_pattern
+        | Rule::tuple_pattern
+        | Rule::record_pattern
+        | Rule::variant_pattern => unreachable!("patterns are handled by parse_pattern"),
+        Rule::match_arm | Rule::guard => unreachable!("handled by parse_match_arm"),
+    };
+    AstNode { kind, span }
 }
@@ -0,0 +1,245 @@
+use std::collections::HashSet;
+
+use pest::Parser as _;
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{
+    ast::{AstKind, AstNode, Span},
+    interpreter::{self, Env, Value},
+    parse::{self, KleyParser, Rule},
+    typeck::Checker,
+};
+
+const PATH: &str = "<repl>";
+
+const HELP: &str = "\
+Enter statements or expressions, the value of a trailing expression is printed.
+Input continues onto the next line while a {, [, ( or \" is left open.
+
+Meta-commands:
+  :type <expr>   show the type of an expression without evaluating it
+  :ast <input>   show the AST of the input (same as --debug-ast)
+  :peg <input>   show the parse tree of the input (same as --debug-peg)
+  :env           show the variables and functions in scope
+  :help          show this message
+  :quit          exit the REPL (or use Ctrl-D)
+";
+
+/// State kept between inputs to the REPL.
+#[derive(Default)]
+struct Repl {
+    env: Env,
+    checker: Checker,
+}
+
+/// Run an interactive REPL until the user exits.
+pub fn run() -> Result<(), String> {
+    let mut editor = DefaultEditor::new().map_err(|e| format!("error: {e}\n"))?;
+    let mut repl = Repl::default();
+    println!("kley {} (:help for help)", env!("CARGO_PKG_VERSION"));
+
+    while let Some(input) = read_input(&mut editor)? {
+        if input.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(input.as_str());
+
+        match input.trim().strip_prefix(':') {
+            Some(meta) => {
+                if !repl.meta(meta) {
+                    break;
+                }
+            }
+            None => repl.eval(&input),
+        }
+    }
+    Ok(())
+}
+
+/// Read one complete input, continuing onto more lines while brackets or strings are open.
+/// Returns None once the user exits with Ctrl-D.
+fn read_input(editor: &mut DefaultEditor) -> Result<Option<String>, String> {
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { ">> " } else { ".. " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+                if !is_incomplete(&input) {
+                    return Ok(Some(input));
+                }
+            }
+            // Ctrl-C discards the current input
+            Err(ReadlineError::Interrupted) => input.clear(),
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(format!("error: {e}\n")),
+        }
+    }
+}
+
+/// Whether the input has an unclosed `{`, `[`, `(` or string.
+///
+/// This only has to be good enough to decide when to keep reading, anything unbalanced in the
+/// other direction is left for the parser to report.
+fn is_incomplete(input: &str) -> bool {
+    let mut open: Vec<char> = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (open.last(), c) {
+            // "{" starts an interpolated block within a string
+            (Some('"'), '"') | (Some('\''), '\'') => {
+                open.pop();
+            }
+            (Some('"'), '{') => open.push('{'),
+            (Some('"' | '\''), _) => {}
+            // raw strings only exist inside commands
+            (Some('['), '\'') => open.push('\''),
+            (_, '"' | '{' | '[' | '(') => open.push(c),
+            (Some('{'), '}') | (Some('['), ']') | (Some('('), ')') => {
+                open.pop();
+            }
+            (_, '/') if chars.peek() == Some(&'/') => {
+                // comments continue to the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    !open.is_empty()
+}
+
+/// Parse the input as a block, printing any parse error.
+fn parse_input(input: &str) -> Option<AstNode> {
+    let span = Span {
+        start: 0,
+        end: input.trim_end().len(),
+        line: 1,
+        col: 1,
+    };
+    KleyParser::parse(Rule::repl_input, input)
+        .and_then(|pairs| parse::build_repl_ast(pairs, span))
+        .map_err(|e| eprintln!("error: failed to parse\n{}", e.with_path(PATH)))
+        .ok()
+}
+
+impl Repl {
+    fn eval(&mut self, input: &str) {
+        let Some(ast) = parse_input(input) else {
+            return;
+        };
+        let AstKind::Block(es) = &ast.kind else {
+            unreachable!()
+        };
+
+        if let Err(errors) = self.checker.check_stmts(es) {
+            for e in errors {
+                eprint!("{}", e.render(input, PATH));
+            }
+            return;
+        }
+
+        self.env.hoist(es);
+        for (i, e) in es.iter().enumerate() {
+            let result = interpreter::eval_stmt(e, &mut self.env).and_then(|out| {
+                // commands are run rather than printed, even as the trailing expression
+                interpreter::run_effects(&out, &self.env, e.span)?;
+                let is_command = matches!(out, Value::Command(_));
+                if i + 1 == es.len() && !is_command && out != Value::Unit {
+                    println!("{}", interpreter::macro_display(out));
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                eprint!("{}", e.diagnostic().render(input, PATH));
+                // the checker has every binding in the input, but only those before the
+                // error were made
+                self.checker = self.env.checker();
+                return;
+            }
+        }
+    }
+
+    /// Handle a meta-command (without the leading `:`), returning false to exit the REPL.
+    fn meta(&mut self, meta: &str) -> bool {
+        let (command, rest) = meta.split_once(char::is_whitespace).unwrap_or((meta, ""));
+        match command {
+            "type" | "t" => {
+                let Some(ast) = parse_input(rest) else {
+                    return true;
+                };
+                let AstKind::Block(es) = &ast.kind else {
+                    unreachable!()
+                };
+                // checked on a copy, so any bindings in the input don't stick around
+                match self.checker.clone().check_stmts(es) {
+                    Ok(Some(t)) => println!("{t}"),
+                    Ok(None) => println!("(unknown)"),
+                    Err(errors) => {
+                        for e in errors {
+                            eprint!("{}", e.render(rest, PATH));
+                        }
+                    }
+                }
+            }
+            "ast" => {
+                if let Some(ast) = parse_input(rest) {
+                    println!("{:#?}", ast);
+                }
+            }
+            "peg" => match KleyParser::parse(Rule::repl_input, rest) {
+                Ok(mut pairs) => parse::display_pairs(&mut pairs, 0),
+                Err(e) => eprintln!("error: failed to parse\n{}", e.with_path(PATH)),
+            },
+            "env" => self.print_env(),
+            "help" | "h" => print!("{HELP}"),
+            "quit" | "q" => return false,
+            _ => eprintln!("error: unknown meta-command `:{command}`, see :help"),
+        }
+        true
+    }
+
+    fn print_env(&self) {
+        let mut fns: Vec<_> = self
+            .env
+            .fns()
+            .filter_map(|f| match &f.kind {
+                AstKind::Function {
+                    name, args, out, ..
+                } => {
+                    let args: Vec<_> = args
+                        .iter()
+                        .map(|param| match param.default {
+                            // the default's source isn't kept, so it's left out
+                            Some(_) => format!("{}: {} = ..", param.pattern, param.ty),
+                            None => format!("{}: {}", param.pattern, param.ty),
+                        })
+                        .collect();
+                    Some(format!("fn {name}({}) -> {out}", args.join(", ")))
+                }
+                _ => None,
+            })
+            .collect();
+        fns.sort();
+        for f in fns {
+            println!("{f}");
+        }
+
+        // only the most recent binding of a name is visible
+        let mut seen = HashSet::new();
+        let mut vars: Vec<_> = self
+            .env
+            .vars()
+            .into_iter()
+            .filter(|(s, _, _)| seen.insert(s.clone()))
+            .collect();
+        vars.reverse();
+        for (name, ty, val) in vars {
+            println!("{name}: {ty} = {}", interpreter::macro_display(val));
+        }
+    }
+}
@@ -0,0 +1,1208 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::*,
+    diagnostic::Diagnostic,
+    exhaustive,
+    types::{Type, TypeEnv},
+};
+
+/// Signature of a user defined function: its arguments (with their types resolved) and the
+/// output type.
+#[derive(Debug, Clone)]
+struct FnSig {
+    args: Vec<Param>,
+    out: Type,
+}
+
+/// Types of everything in scope, mirroring interpreter::Env.
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    vars: Vec<(String, Type)>,
+    fns: HashMap<String, FnSig>,
+    types: TypeEnv,
+    /// Number of loops enclosing the current statement, for `break` and `continue`.
+    loops: usize,
+    /// Output type of the enclosing function or closure (if any), for `return`.
+    ret: Option<Type>,
+}
+
+impl Scope {
+    fn lookup(&self, var: &str) -> Option<&Type> {
+        self.vars.iter().find(|(s, _)| var == s).map(|(_, t)| t)
+    }
+}
+
+/// Static type checker, run over the whole program before anything is evaluated.
+///
+/// Types are inferred bottom-up, and checked against annotations (bindings, function arguments
+/// and outputs) using `Type::converts_to`. Expressions whose type can't be determined are given
+/// `None` and not checked any further, so each mistake is only reported once.
+#[derive(Debug, Clone, Default)]
+pub struct Checker {
+    scope: Scope,
+    errors: Vec<Diagnostic>,
+}
+
+/// Check a whole program, returning every type error found.
+pub fn check(ast: &AstNode) -> Result<(), Vec<Diagnostic>> {
+    let mut checker = Checker::default();
+    checker.infer(ast);
+    match checker.errors.is_empty() {
+        true => Ok(()),
+        false => Err(checker.errors),
+    }
+}
+
+impl Checker {
+    /// Check the statements of a block within the current scope, keeping any new bindings so
+    /// later input can use them. On failure the scope is left unchanged.
+    ///
+    /// Used by the REPL, where each input is checked separately.
+    pub fn check_stmts(&mut self, stmts: &[AstNode]) -> Result<Option<Type>, Vec<Diagnostic>> {
+        let outer = self.scope.clone();
+        self.hoist(stmts);
+        let mut out = Some(Type::Unit);
+        for e in stmts {
+            out = self.stmt(e);
+        }
+        if self.errors.is_empty() {
+            Ok(out)
+        } else {
+            self.scope = outer;
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Type of an expression in the current scope, ignoring any errors within it.
+    pub fn type_of(&self, exp: &AstNode) -> Option<Type> {
+        self.clone().infer(exp)
+    }
+
+    pub fn var_type(&self, var: &str) -> Option<&Type> {
+        self.scope.lookup(var)
+    }
+
+    /// Output type of the function or closure being checked, if any.
+    pub fn return_type(&self) -> Option<&Type> {
+        self.scope.ret.as_ref()
+    }
+
+    /// Argument and output types of a user defined function.
+    pub fn fn_signature(&self, name: &str) -> Option<(&[Param], &Type)> {
+        let sig = self.scope.fns.get(name)?;
+        Some((&sig.args, &sig.out))
+    }
+
+    pub fn bind(&mut self, ident: &str, ty: &Type) {
+        self.scope
+            .vars
+            .insert(0, (ident.to_string(), ty.unfold().clone()));
+    }
+
+    /// Declare the types and functions in a block before checking any of it, so they can be used
+    /// anywhere within the block and refer to each other (as in mutually recursive functions).
+    pub fn hoist(&mut self, stmts: &[AstNode]) {
+        let decls: Vec<_> = stmts
+            .iter()
+            .filter_map(|e| match &e.kind {
+                AstKind::TypeDecl { name, ty } => Some((name, ty, e.span)),
+                _ => None,
+            })
+            .collect();
+        for (i, (name, ty, span)) in decls.iter().enumerate() {
+            if decls[..i].iter().any(|(other, _, _)| other == name) {
+                self.error(format!("type `{name}` is already declared"), *span);
+            } else {
+                self.scope.types.declare(name, ty);
+            }
+        }
+        for (name, _, span) in decls {
+            if let Err(message) = self.scope.types.resolve(&Type::Named(name.clone())) {
+                self.error(message, span);
+            }
+        }
+
+        let mut fns = Vec::new();
+        for e in stmts {
+            let AstKind::Function {
+                name, args, out, ..
+            } = &e.kind
+            else {
+                continue;
+            };
+            if fns.contains(&name) {
+                self.error(format!("function `{name}` is already defined"), e.span);
+                continue;
+            }
+            fns.push(name);
+            // only to report unknown types, which are left as they are by fn_types
+            for ty in args.iter().map(|param| &param.ty).chain([out]) {
+                self.resolve(ty, e.span);
+            }
+            let (args, out) = self.fn_types(args, out);
+            self.declare_fn(name, &args, &out);
+        }
+    }
+
+    /// Arguments and output type of a function, with the names in their types resolved.
+    fn fn_types(&self, args: &[Param], out: &Type) -> (Vec<Param>, Type) {
+        let resolve = |ty: &Type| self.scope.types.resolve(ty).unwrap_or_else(|_| ty.clone());
+        let args = args
+            .iter()
+            .map(|param| Param {
+                ty: resolve(&param.ty),
+                ..param.clone()
+            })
+            .collect();
+        (args, resolve(out))
+    }
+
+    /// A type as written in the program, with the names in it resolved. Unknown names are
+    /// reported and left as they are.
+    fn resolve(&mut self, ty: &Type, span: Span) -> Type {
+        match self.scope.types.resolve(ty) {
+            Ok(ty) => ty,
+            Err(message) => {
+                self.error(message, span);
+                ty.clone()
+            }
+        }
+    }
+
+    /// Use types declared elsewhere, such as by a program which is already running.
+    pub fn use_types(&mut self, types: &TypeEnv) {
+        self.scope.types = types.clone();
+    }
+
+    /// A type as written in the program which has already been checked.
+    pub fn resolved(&self, ty: &Type) -> Type {
+        self.scope.types.resolve(ty).expect("checked by typeck")
+    }
+
+    pub fn declare_fn(&mut self, name: &str, args: &[Param], out: &Type) {
+        let sig = FnSig {
+            args: args.to_vec(),
+            out: out.clone(),
+        };
+        self.scope.fns.insert(name.to_string(), sig);
+    }
+
+    /// Function bodies only see their arguments, not the surrounding variables.
+    pub fn enter_function(&mut self, args: &[Param], out: &Type, span: Span) {
+        self.scope.vars.clear();
+        self.scope.loops = 0;
+        self.scope.ret = Some(out.clone());
+        for param in args {
+            if let Some(default) = &param.default {
+                self.expect(default, &param.ty);
+            }
+            self.bind_pattern(&param.pattern, &param.ty, span);
+        }
+    }
+
+    /// Bind each name in a pattern to the matching part of `ty`. Outside of a `match` there's
+    /// nothing to do with values the pattern doesn't match, so it has to match all of them.
+    pub fn bind_pattern(&mut self, pattern: &Pattern, ty: &Type, span: Span) {
+        if !self.check_pattern(pattern, ty, span) {
+            return;
+        }
+        if let Some(missing) = exhaustive::missing(&[pattern], ty) {
+            self.error(
+                format!(
+                    "pattern `{pattern}` doesn't match every `{ty}`, such as `{missing}`; use \
+                     `match` to handle the rest"
+                ),
+                span,
+            );
+        }
+    }
+
+    /// Bind each name in a pattern to the matching part of `ty`, returning whether the pattern
+    /// fits the type.
+    fn check_pattern(&mut self, pattern: &Pattern, ty: &Type, span: Span) -> bool {
+        let ty = ty.unfold();
+        let parts: Vec<(&Pattern, &Type)> = match (pattern, ty) {
+            (Pattern::Ident(ident), ty) => {
+                self.bind(ident, ty);
+                return true;
+            }
+            (Pattern::Wildcard, _)
+            | (Pattern::Int(_), Type::Int)
+            | (Pattern::Str(_), Type::Str)
+            | (Pattern::Bool(_), Type::Bool) => return true,
+            (Pattern::Tuple(ps), Type::Tuple(ts)) if ps.len() == ts.len() => {
+                ps.iter().zip(ts).collect()
+            }
+            (Pattern::Record(fields), Type::Record(ts))
+                if fields.iter().all(|(name, _)| ts.contains_key(name)) =>
+            {
+                fields.iter().map(|(name, p)| (p, &ts[name])).collect()
+            }
+            (Pattern::Variant { tag, pattern }, Type::Variant(ts)) if ts.contains_key(tag) => {
+                vec![(&**pattern, &ts[tag])]
+            }
+            (pattern, ty) => {
+                self.error(format!("pattern `{pattern}` doesn't match `{ty}`"), span);
+                // still bind the names, so they aren't reported as unknown
+                for ident in pattern.idents() {
+                    self.scope.vars.insert(0, (ident.to_string(), ty.clone()));
+                }
+                return false;
+            }
+        };
+        let mut fits = true;
+        for (p, t) in parts {
+            fits &= self.check_pattern(p, t, span);
+        }
+        fits
+    }
+
+    /// Check the arms of a `match` on a value of type `ty`, giving the type of each arm (or
+    /// checking them against `expected`).
+    fn match_arms(
+        &mut self,
+        arms: &[MatchArm],
+        ty: &Type,
+        expected: Option<&Type>,
+        span: Span,
+    ) -> Vec<Option<Type>> {
+        let mut fits = true;
+        let mut types = Vec::new();
+        for arm in arms {
+            let outer = self.scope.clone();
+            fits &= self.check_pattern(&arm.pattern, ty, arm.span);
+            if let Some(guard) = &arm.guard {
+                self.expect_cond(guard, "if");
+            }
+            types.push(match expected {
+                Some(expected) => {
+                    self.expect(&arm.body, expected);
+                    Some(expected.clone())
+                }
+                None => self.infer(&arm.body),
+            });
+            self.scope = outer;
+        }
+        // an arm with a guard might not be taken, so it doesn't count towards covering a value
+        let patterns: Vec<_> = arms
+            .iter()
+            .filter(|arm| arm.guard.is_none())
+            .map(|arm| &arm.pattern)
+            .collect();
+        let message = match exhaustive::missing(&patterns, ty) {
+            _ if !fits => return types,
+            None => return types,
+            Some(Pattern::Wildcard) => {
+                format!("`match` doesn't cover every `{ty}`, add a `_` arm for the rest")
+            }
+            Some(missing) => {
+                format!("`match` doesn't cover every `{ty}`, `{missing}` isn't matched")
+            }
+        };
+        self.error(message, span);
+        types
+    }
+
+    fn error(&mut self, message: impl Into<String>, span: Span) {
+        self.errors.push(Diagnostic::new(message, span));
+    }
+
+    fn mismatch(&mut self, expected: &Type, found: &Type, span: Span) {
+        // a name which is still there after resolving has already been reported as unknown
+        if let Type::Named(_) = expected {
+            return;
+        }
+        self.error(
+            format!("mismatched types: expected `{expected}`, found `{found}`"),
+            span,
+        );
+    }
+
+    /// Check that an expression can be converted to the expected type.
+    ///
+    /// Branching expressions pass the expected type down, so errors point at the branch which
+    /// produced the wrong type rather than the whole expression.
+    fn expect(&mut self, exp: &AstNode, expected: &Type) {
+        let expected = expected.unfold();
+        match &exp.kind {
+            AstKind::IfThenElse {
+                cond,
+                t_block,
+                f_block,
+            } => {
+                self.expect_cond(cond, "if");
+                self.expect(t_block, expected);
+                match f_block.kind {
+                    AstKind::Unit if !Type::Unit.converts_to(expected) => self.error(
+                        format!(
+                            "`if` without an `else` produces `unit`, but `{expected}` is \
+                             expected here; add an `else`"
+                        ),
+                        f_block.span,
+                    ),
+                    _ => self.expect(f_block, expected),
+                }
+            }
+            AstKind::TryCatch {
+                block,
+                ident,
+                catch,
+            } => {
+                self.expect(block, expected);
+                self.expect_catch(ident, catch, expected);
+                self.check_try_value(exp, block);
+            }
+            // each item is converted separately, which also gives empty lists a type
+            AstKind::ListValue(es) if matches!(expected, Type::List(_)) => {
+                let Type::List(t) = expected else {
+                    unreachable!()
+                };
+                for e in es {
+                    self.expect(e, t);
+                }
+            }
+            AstKind::TupleValue(es) if matches!(expected, Type::Tuple(ts) if ts.len() == es.len()) =>
+            {
+                let Type::Tuple(ts) = expected else {
+                    unreachable!()
+                };
+                for (e, t) in es.iter().zip(ts) {
+                    self.expect(e, t);
+                }
+            }
+            // fields which aren't expected are still allowed, as with `record_is_subtype`
+            AstKind::RecordValue(fields) if matches!(expected, Type::Record(ts) if ts.keys().all(|k| fields.contains_key(k))) =>
+            {
+                let Type::Record(ts) = expected else {
+                    unreachable!()
+                };
+                for (name, e) in fields {
+                    match ts.get(name) {
+                        Some(t) => self.expect(e, t),
+                        None => {
+                            self.infer(e);
+                        }
+                    }
+                }
+            }
+            AstKind::VariantValue { tag, expr } if matches!(expected, Type::Variant(_)) => {
+                let Type::Variant(tags) = expected else {
+                    unreachable!()
+                };
+                match tags.get(tag) {
+                    Some(t) => self.expect(expr, t),
+                    None => {
+                        self.error(format!("`{expected}` has no variant `{tag}`"), exp.span);
+                        self.infer(expr);
+                    }
+                }
+            }
+            AstKind::Match { expr, arms } => {
+                if let Some(ty) = self.infer(expr) {
+                    self.match_arms(arms, &ty, Some(expected), exp.span);
+                }
+            }
+            AstKind::MapValue(entries) if matches!(expected, Type::Map(_, _)) => {
+                let Type::Map(k, v) = expected else {
+                    unreachable!()
+                };
+                for (key, value) in entries {
+                    self.expect(key, k);
+                    self.expect(value, v);
+                }
+            }
+            AstKind::Block(es) => {
+                let outer = self.scope.clone();
+                self.hoist(es);
+                if let Some((last, stmts)) = es.split_last() {
+                    for e in stmts {
+                        self.stmt(e);
+                    }
+                    // the value is never reached after a `return`, so it can be anything
+                    match stmts.iter().any(returns) {
+                        true => {
+                            self.stmt(last);
+                        }
+                        false => self.expect(last, expected),
+                    }
+                }
+                self.scope = outer;
+            }
+            _ => {
+                if let Some(found) = self.infer(exp) {
+                    if !found.converts_to(expected) {
+                        self.mismatch(expected, &found, exp.span);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check the catch block of a `try`, with the error bound to `ident`.
+    fn expect_catch(&mut self, ident: &str, catch: &AstNode, expected: &Type) {
+        let outer = self.scope.clone();
+        self.bind(ident, &Type::error_record());
+        self.expect(catch, expected);
+        self.scope = outer;
+    }
+
+    /// A command produced by a `try` block would only be run after leaving the `try`, so
+    /// failures wouldn't be caught.
+    fn check_try_value(&mut self, exp: &AstNode, block: &AstNode) {
+        if self.type_of(block) == Some(Type::Command) {
+            self.error(
+                "a `try` block can't produce a `command`, as it would run outside of the `try`; \
+                 end it with `;` to run it inside",
+                exp.span,
+            );
+        }
+    }
+
+    fn expect_cond(&mut self, cond: &AstNode, keyword: &str) {
+        match self.infer(cond) {
+            Some(Type::Bool) | None => {}
+            Some(t) => self.error(
+                format!("`{keyword}` condition must be a `bool`, found `{t}`"),
+                cond.span,
+            ),
+        }
+    }
+
+    /// Type of an `if` whose branches have the types `t` and `f`, without an expected type to
+    /// convert both to. The branches have to agree, as described by `branch_type`.
+    fn if_type(&mut self, t: Type, f: Type, f_block: &AstNode, span: Span) -> Option<Type> {
+        if let Some(out) = branch_type(&t, &f) {
+            return Some(out);
+        }
+        let message = match f_block.kind {
+            AstKind::Unit => format!(
+                "`if` without an `else` produces `unit`, but its block produces `{t}`; \
+                 add an `else` or end the block with `;`"
+            ),
+            _ => format!("`if` and `else` have incompatible types: `{t}` and `{f}`"),
+        };
+        self.error(message, span);
+        None
+    }
+
+    /// Type shared by every expression, such as the items of a list literal. `what` names the
+    /// expressions for errors.
+    fn common_type<'a>(
+        &mut self,
+        es: impl IntoIterator<Item = &'a AstNode>,
+        what: &str,
+    ) -> Option<Type> {
+        let mut out = None;
+        let mut known = true;
+        for e in es {
+            let Some(found) = self.infer(e) else {
+                known = false;
+                continue;
+            };
+            match &out {
+                None => out = Some(found),
+                Some(t) if *t != found => {
+                    self.error(
+                        format!("{what} have different types: `{t}` and `{found}`"),
+                        e.span,
+                    );
+                    known = false;
+                }
+                Some(_) => {}
+            }
+        }
+        match known {
+            true => out,
+            false => None,
+        }
+    }
+
+    /// Key and value types of a map given to a builtin.
+    fn map_type(&mut self, exp: &AstNode, builtin: &str) -> Option<(Type, Type)> {
+        match self.infer(exp)? {
+            Type::Map(k, v) => Some((*k, *v)),
+            t => {
+                self.error(format!("`{builtin}` takes a map, found `{t}`"), exp.span);
+                None
+            }
+        }
+    }
+
+    /// Check that a key has the type of a map's keys. They aren't converted, since the map
+    /// itself doesn't know its key type at runtime.
+    fn expect_key(&mut self, key: &AstNode, expected: &Type) {
+        match self.infer(key) {
+            Some(t) if t != *expected => self.mismatch(expected, &t, key.span),
+            _ => {}
+        }
+    }
+
+    /// Key and value types of the map variable updated by `insert` or `remove`.
+    fn map_var(&mut self, exp: &AstNode, builtin: &str) -> Option<(Type, Type)> {
+        if !matches!(exp.kind, AstKind::Ident(_)) {
+            self.error(
+                format!("`{builtin}` updates a variable, so it has to be given one"),
+                exp.span,
+            );
+            self.infer(exp);
+            return None;
+        }
+        self.map_type(exp, builtin)
+    }
+
+    /// Type of the items of a list which is being indexed or sliced.
+    fn list_type(&mut self, exp: &AstNode) -> Option<Type> {
+        match self.infer(exp)? {
+            Type::List(t) => Some(*t),
+            t => {
+                self.error(
+                    format!("cannot index into `{t}`, expected a list"),
+                    exp.span,
+                );
+                None
+            }
+        }
+    }
+
+    fn expect_index(&mut self, index: &AstNode) {
+        match self.infer(index) {
+            Some(Type::Int) | None => {}
+            Some(t) => self.error(
+                format!("list indices must be an `int`, found `{t}`"),
+                index.span,
+            ),
+        }
+    }
+
+    /// Check the body of a loop, with the loop variable (if any) bound.
+    fn loop_body(&mut self, block: &AstNode, var: Option<(&Pattern, &Type, Span)>) {
+        let outer = self.scope.clone();
+        if let Some((pattern, ty, span)) = var {
+            self.bind_pattern(pattern, ty, span);
+        }
+        self.scope.loops += 1;
+        self.infer(block);
+        self.scope = outer;
+    }
+
+    /// Type of the items `for` iterates over, mirroring interpreter::eval_items.
+    fn item_type(&mut self, iter: &AstNode) -> Option<Type> {
+        match self.infer(iter)? {
+            Type::List(t) => Some(*t),
+            Type::Map(k, _) => Some(*k),
+            Type::Command | Type::Process | Type::Output => Some(Type::Str),
+            t => {
+                self.error(
+                    format!("cannot iterate over `{t}`, expected a list, map or command"),
+                    iter.span,
+                );
+                None
+            }
+        }
+    }
+
+    /// Check a statement within a block, updating the scope with any new bindings.
+    fn stmt(&mut self, e: &AstNode) -> Option<Type> {
+        match &e.kind {
+            AstKind::Binding { pattern, ty, expr } => {
+                let ty = self.resolve(ty, e.span);
+                self.expect(expr, &ty);
+                self.bind_pattern(pattern, &ty, e.span);
+                Some(Type::Unit)
+            }
+            AstKind::Assign { ident, expr } => {
+                match self.scope.lookup(ident).cloned() {
+                    Some(ty) => self.expect(expr, &ty),
+                    None => {
+                        self.error(
+                            format!("cannot assign to `{ident}`, it was never bound with `let`"),
+                            e.span,
+                        );
+                        self.infer(expr);
+                    }
+                }
+                Some(Type::Unit)
+            }
+            // declared by Checker::hoist, so it can be called from anywhere in the block
+            AstKind::Function {
+                args, out, block, ..
+            } => {
+                let (args, out) = self.fn_types(args, out);
+                let outer = self.scope.clone();
+                self.enter_function(&args, &out, e.span);
+                self.expect(block, &out);
+                self.scope = outer;
+                Some(Type::Unit)
+            }
+            AstKind::While { cond, block } => {
+                self.expect_cond(cond, "while");
+                self.loop_body(block, None);
+                Some(Type::Unit)
+            }
+            AstKind::For {
+                pattern,
+                ty,
+                iter,
+                block,
+            } => {
+                let ty = self.resolve(ty, e.span);
+                if let Some(item) = self.item_type(iter) {
+                    if !item.converts_to(&ty) {
+                        self.mismatch(&ty, &item, iter.span);
+                    }
+                }
+                self.loop_body(block, Some((pattern, &ty, e.span)));
+                Some(Type::Unit)
+            }
+            AstKind::Break | AstKind::Continue => {
+                if self.scope.loops == 0 {
+                    let keyword = match e.kind {
+                        AstKind::Break => "break",
+                        _ => "continue",
+                    };
+                    self.error(format!("`{keyword}` outside of a loop"), e.span);
+                }
+                Some(Type::Unit)
+            }
+            AstKind::Return(expr) => {
+                match self.scope.ret.clone() {
+                    Some(out) => self.expect(expr, &out),
+                    None => {
+                        self.error("`return` outside of a function", e.span);
+                        self.infer(expr);
+                    }
+                }
+                Some(Type::Unit)
+            }
+            // declared along with the rest of the block
+            AstKind::TypeDecl { .. } => Some(Type::Unit),
+            AstKind::Call { name, args, named }
+                if name == "errexit" && args.len() == 1 && named.is_empty() =>
+            {
+                self.expect(&args[0], &Type::Bool);
+                Some(Type::Unit)
+            }
+            _ => self.infer(e),
+        }
+    }
+
+    /// Infer the type of an expression, reporting any errors found within it.
+    ///
+    /// Recursive types are unfolded, so the type can be matched on directly.
+    pub fn infer(&mut self, exp: &AstNode) -> Option<Type> {
+        self.infer_inner(exp).map(|t| t.unfold().clone())
+    }
+
+    fn infer_inner(&mut self, exp: &AstNode) -> Option<Type> {
+        match &exp.kind {
+            AstKind::Unit => Some(Type::Unit),
+            AstKind::Integer(_) => Some(Type::Int),
+            AstKind::Float(_) => Some(Type::Float),
+            AstKind::Boolean(_) => Some(Type::Bool),
+            AstKind::StringLiteral(_) => Some(Type::Str),
+            AstKind::QuoteString(parts) => {
+                for part in parts {
+                    self.expect(part, &Type::Str);
+                }
+                Some(Type::Str)
+            }
+            AstKind::Format { expr, .. } => {
+                self.expect(expr, &Type::Float);
+                Some(Type::Str)
+            }
+            AstKind::Ident(var) => match self.scope.lookup(var) {
+                Some(t) => Some(t.clone()),
+                // a function used by name is a value like any other
+                None if self.scope.fns.contains_key(var) => {
+                    let sig = &self.scope.fns[var];
+                    let args = sig.args.iter().map(|param| param.ty.clone()).collect();
+                    Some(Type::Fn(args, Box::new(sig.out.clone())))
+                }
+                None => {
+                    self.error(format!("unknown identifier `{var}`"), exp.span);
+                    None
+                }
+            },
+            AstKind::PrefixExpr { verb, expr } => match (verb, self.infer(expr)?) {
+                (PrefixVerb::Negate, t @ (Type::Int | Type::Float))
+                | (PrefixVerb::Not, t @ Type::Bool) => Some(t),
+                (_, t) => {
+                    self.error(format!("cannot apply `{verb}` to `{t}`"), exp.span);
+                    None
+                }
+            },
+            AstKind::InfixExpr { verb, lhs, rhs } => {
+                let t1 = self.infer(lhs);
+                let t2 = self.infer(rhs);
+                let (t1, t2) = (t1?, t2?);
+                let out = infix_type(verb, &t1, &t2);
+                if out.is_none() {
+                    self.error(
+                        format!("cannot apply `{verb}` to `{t1}` and `{t2}`"),
+                        exp.span,
+                    );
+                }
+                out
+            }
+            AstKind::Command { tokens, redirects } => {
+                // a `list<str>` given to `<<<` also converts to `str`, so needs no special case
+                let targets = redirects.iter().filter_map(|r| r.target.as_ref());
+                for part in tokens.iter().chain(targets).flat_map(|tok| &tok.parts) {
+                    self.expect(part, &Type::Str);
+                }
+                Some(Type::Command)
+            }
+            AstKind::Block(es) => {
+                let outer = self.scope.clone();
+                self.hoist(es);
+                let mut out = Some(Type::Unit);
+                for e in es {
+                    out = self.stmt(e);
+                }
+                self.scope = outer;
+                out
+            }
+            AstKind::IfThenElse {
+                cond,
+                t_block,
+                f_block,
+            } => {
+                self.expect_cond(cond, "if");
+                let (t, f) = (self.infer(t_block)?, self.infer(f_block)?);
+                // a branch which returns doesn't produce a value, so the other decides the type
+                match (returns(t_block), returns(f_block)) {
+                    (true, _) => Some(f),
+                    (_, true) => Some(t),
+                    _ => self.if_type(t, f, f_block, exp.span),
+                }
+            }
+            AstKind::TryCatch {
+                block,
+                ident,
+                catch,
+            } => {
+                let t = self.infer(block);
+                let outer = self.scope.clone();
+                self.bind(ident, &Type::error_record());
+                let f = self.infer(catch);
+                self.scope = outer;
+                self.check_try_value(exp, block);
+                match (t, f) {
+                    (Some(t), Some(f)) if t == f => Some(t),
+                    _ => None,
+                }
+            }
+            // other errors (such as failed conversions) always propagate, so `?` would do nothing
+            AstKind::Propagate(expr) => match self.infer(expr)? {
+                Type::Command => Some(Type::Command),
+                Type::Process | Type::Output => Some(Type::Output),
+                t => {
+                    self.error(
+                        format!("`?` can only be applied to a `command`, `process` or `output`, found `{t}`"),
+                        exp.span,
+                    );
+                    None
+                }
+            },
+            AstKind::ListValue(es) => {
+                if es.is_empty() {
+                    self.error(
+                        "cannot infer the type of an empty list, give it one with `let` \
+                         (as in `let xs: list<int> = [];`)",
+                        exp.span,
+                    );
+                    return None;
+                }
+                let t = self.common_type(es, "list items")?;
+                Some(Type::List(Box::new(t)))
+            }
+            AstKind::MapValue(entries) => {
+                if entries.is_empty() {
+                    self.error(
+                        "cannot infer the type of an empty map, give it one with `let` \
+                         (as in `let m: map<str, int> = #{};`)",
+                        exp.span,
+                    );
+                    return None;
+                }
+                let k = self.common_type(entries.iter().map(|(k, _)| k), "map keys");
+                let v = self.common_type(entries.iter().map(|(_, v)| v), "map values");
+                Some(Type::Map(Box::new(k?), Box::new(v?)))
+            }
+            AstKind::TupleValue(es) => {
+                let ts: Vec<_> = es.iter().map(|e| self.infer(e)).collect();
+                Some(Type::Tuple(ts.into_iter().collect::<Option<_>>()?))
+            }
+            AstKind::TupleIndex { expr, index } => match self.infer(expr)? {
+                Type::Tuple(ts) if *index < ts.len() => Some(ts[*index].clone()),
+                t => {
+                    self.error(format!("`{t}` has no item `{index}`"), exp.span);
+                    None
+                }
+            },
+            AstKind::Index { expr, index } => {
+                let t = self.list_type(expr);
+                self.expect_index(index);
+                t
+            }
+            AstKind::Slice { expr, start, end } => {
+                let t = self.list_type(expr);
+                self.expect_index(start);
+                self.expect_index(end);
+                Some(Type::List(Box::new(t?)))
+            }
+            AstKind::RecordValue(r) => {
+                let mut out = HashMap::new();
+                let mut known = true;
+                for (key, ast) in r.iter() {
+                    match self.infer(ast) {
+                        Some(t) => {
+                            out.insert(key.clone(), t);
+                        }
+                        None => known = false,
+                    }
+                }
+                known.then_some(Type::Record(out))
+            }
+            AstKind::RecordUpdate { base, fields } => {
+                let mut known = true;
+                let mut out = match self.infer(base) {
+                    Some(Type::Record(r)) => r,
+                    Some(t) => {
+                        self.error(format!("`..` takes a record, found `{t}`"), base.span);
+                        return None;
+                    }
+                    None => {
+                        known = false;
+                        HashMap::new()
+                    }
+                };
+                // replaced fields keep their type, whereas new fields are added with their own
+                for (name, e) in fields {
+                    match out.get(name).cloned() {
+                        Some(t) => self.expect(e, &t),
+                        None => match self.infer(e) {
+                            Some(t) => {
+                                out.insert(name.clone(), t);
+                            }
+                            None => known = false,
+                        },
+                    }
+                }
+                known.then_some(Type::Record(out))
+            }
+            AstKind::Field { expr, name } => {
+                let ty = self.infer(expr)?;
+                // commands are run to get at their output's fields
+                let record = match &ty {
+                    Type::Command | Type::Process | Type::Output => Type::output_record(),
+                    t => t.clone(),
+                };
+                let field = match &record {
+                    Type::Record(fields) => fields.get(name).cloned(),
+                    _ => None,
+                };
+                if field.is_none() {
+                    self.error(format!("`{ty}` has no field `{name}`"), exp.span);
+                }
+                field
+            }
+            AstKind::VariantValue { tag, expr } => Some(Type::Variant(HashMap::from([(
+                tag.clone(),
+                self.infer(expr)?,
+            )]))),
+            AstKind::Match { expr, arms } => {
+                let ty = self.infer(expr)?;
+                let types = self.match_arms(arms, &ty, None, exp.span);
+                let mut out: Option<Type> = None;
+                for (arm, t) in arms.iter().zip(types) {
+                    let t = t?;
+                    out = match out {
+                        None => Some(t),
+                        Some(out) => {
+                            match branch_type(&out, &t) {
+                                Some(t) => Some(t),
+                                None => {
+                                    self.error(
+                                    format!("`match` arms have incompatible types: `{out}` and `{t}`"),
+                                    arm.body.span,
+                                );
+                                    return None;
+                                }
+                            }
+                        }
+                    };
+                }
+                out
+            }
+            AstKind::Call { name, args, named } => self.call(name, args, named, exp.span),
+            AstKind::Closure { args, out, block } => {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|(pattern, ty)| (pattern.clone(), self.resolve(ty, exp.span)))
+                    .collect();
+                let out = self.resolve(out, exp.span);
+
+                // unlike a function, the body sees the variables around it
+                let outer = self.scope.clone();
+                self.scope.loops = 0;
+                self.scope.ret = Some(out.clone());
+                for (pattern, ty) in &args {
+                    self.bind_pattern(pattern, ty, exp.span);
+                }
+                self.expect(block, &out);
+                self.scope = outer;
+                let args = args.into_iter().map(|(_, ty)| ty).collect();
+                Some(Type::Fn(args, Box::new(out)))
+            }
+            AstKind::Apply { expr, args } => match self.infer(expr) {
+                Some(Type::Fn(params, out)) => {
+                    self.call_args("this function", &params, args, exp.span);
+                    Some(*out)
+                }
+                t => {
+                    if let Some(t) = t {
+                        self.error(format!("cannot call `{t}`, it isn't a function"), expr.span);
+                    }
+                    for arg in args {
+                        self.infer(arg);
+                    }
+                    None
+                }
+            },
+            AstKind::Binding { .. }
+            | AstKind::Assign { .. }
+            | AstKind::Function { .. }
+            | AstKind::While { .. }
+            | AstKind::For { .. }
+            | AstKind::Break
+            | AstKind::Continue
+            | AstKind::Return(_)
+            | AstKind::TypeDecl { .. } => {
+                unreachable!("statements are handled by Checker::stmt")
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[AstNode],
+        named: &[(String, AstNode)],
+        span: Span,
+    ) -> Option<Type> {
+        match (name, args) {
+            // builtins only take positional arguments
+            _ if !named.is_empty() => {}
+            ("display", [e]) => {
+                self.infer(e);
+                return Some(Type::Str);
+            }
+            ("len", [e]) => {
+                match self.infer(e) {
+                    Some(Type::List(_) | Type::Map(_, _) | Type::Str) | None => {}
+                    Some(t) => self.error(
+                        format!("`len` takes a list, map or `str`, found `{t}`"),
+                        e.span,
+                    ),
+                }
+                return Some(Type::Int);
+            }
+            ("lookup", [m, key]) | ("lookup", [m, key, _]) => {
+                let (k, v) = self.map_type(m, name)?;
+                self.expect_key(key, &k);
+                if let [_, _, default] = args {
+                    self.expect(default, &v);
+                }
+                return Some(v);
+            }
+            ("contains", [xs, x]) => {
+                match self.infer(xs) {
+                    Some(Type::Map(k, _) | Type::List(k)) => self.expect_key(x, &k),
+                    Some(t) => {
+                        let message = format!("`contains` takes a list or a map, found `{t}`");
+                        self.error(message, xs.span);
+                    }
+                    None => {}
+                }
+                return Some(Type::Bool);
+            }
+            ("keys", [m]) => return Some(Type::List(Box::new(self.map_type(m, name)?.0))),
+            ("values", [m]) => return Some(Type::List(Box::new(self.map_type(m, name)?.1))),
+            ("insert", [m, key, value]) => {
+                let (k, v) = self.map_var(m, name)?;
+                self.expect(key, &k);
+                self.expect(value, &v);
+                return Some(Type::Unit);
+            }
+            ("remove", [m, key]) => {
+                let (k, _) = self.map_var(m, name)?;
+                self.expect(key, &k);
+                return Some(Type::Unit);
+            }
+            ("allow_fail", [e]) => {
+                self.expect(e, &Type::Command);
+                return Some(Type::Command);
+            }
+            ("errexit", _) => {
+                // handled by Checker::stmt otherwise
+                self.error("`errexit` can only be used as a statement", span);
+                return None;
+            }
+            _ => {}
+        }
+        // a variable holding a function shadows a function with its name, but other variables
+        // don't, so `let total: int = total(xs);` still works
+        let var = self.scope.lookup(name).cloned();
+        if let Some(Type::Fn(params, out)) = var {
+            if let Some((_, arg)) = named.first() {
+                let message = format!(
+                    "`{name}` is a function value, so it can't be called with named arguments"
+                );
+                self.error(message, arg.span);
+            }
+            self.call_args(&format!("`{name}`"), &params, args, span);
+            return Some(*out);
+        }
+        let Some(sig) = self.scope.fns.get(name).cloned() else {
+            match var {
+                Some(t) => self.error(format!("cannot call `{name}`, it's a `{t}`"), span),
+                None => self.error(format!("unknown function `{name}`"), span),
+            }
+            for arg in args.iter().chain(named.iter().map(|(_, arg)| arg)) {
+                self.infer(arg);
+            }
+            return None;
+        };
+        self.fn_call_args(name, &sig.args, args, named, span);
+        Some(sig.out)
+    }
+
+    /// Check the arguments of a call to the function `name`, which can leave out arguments with
+    /// a default and give any argument by name after the positional ones.
+    fn fn_call_args(
+        &mut self,
+        name: &str,
+        params: &[Param],
+        args: &[AstNode],
+        named: &[(String, AstNode)],
+        span: Span,
+    ) {
+        if args.len() > params.len() {
+            let message = format!(
+                "`{name}` takes {} argument(s), but {} were given",
+                params.len(),
+                args.len()
+            );
+            self.error(message, span);
+        }
+        for (arg, param) in args.iter().zip(params) {
+            self.expect(arg, &param.ty);
+        }
+
+        let mut given: Vec<_> = (0..args.len()).collect();
+        for (arg_name, arg) in named {
+            let pattern = Pattern::Ident(arg_name.clone());
+            let Some(i) = params.iter().position(|p| p.pattern == pattern) else {
+                self.error(format!("`{name}` has no argument `{arg_name}`"), arg.span);
+                self.infer(arg);
+                continue;
+            };
+            if given.contains(&i) {
+                let message = format!("argument `{arg_name}` is given more than once");
+                self.error(message, arg.span);
+            }
+            given.push(i);
+            self.expect(arg, &params[i].ty);
+        }
+
+        let missing: Vec<_> = params
+            .iter()
+            .enumerate()
+            .filter(|(i, p)| p.default.is_none() && !given.contains(i))
+            .map(|(_, p)| format!("`{}`", p.pattern))
+            .collect();
+        if !missing.is_empty() {
+            let message = format!("`{name}` is missing the argument(s) {}", missing.join(", "));
+            self.error(message, span);
+        }
+    }
+
+    /// Check the arguments of a call to `what` (such as a function's name), which takes
+    /// arguments of the types `params`.
+    fn call_args(&mut self, what: &str, params: &[Type], args: &[AstNode], span: Span) {
+        if args.len() != params.len() {
+            self.error(
+                format!(
+                    "{what} takes {} argument(s), but {} were given",
+                    params.len(),
+                    args.len()
+                ),
+                span,
+            );
+        }
+        for (arg, ty) in args.iter().zip(params) {
+            self.expect(arg, ty);
+        }
+    }
+}
+
+/// Whether an expression always leaves its function with `return`, rather than producing a
+/// value. Loops aren't looked into, as they might not run at all.
+fn returns(e: &AstNode) -> bool {
+    match &e.kind {
+        AstKind::Return(_) => true,
+        AstKind::Block(es) => es.iter().any(returns),
+        AstKind::IfThenElse {
+            t_block, f_block, ..
+        } => returns(t_block) && returns(f_block),
+        AstKind::Match { arms, .. } => arms.iter().all(|arm| returns(&arm.body)),
+        AstKind::TryCatch { block, catch, .. } => returns(block) && returns(catch),
+        _ => false,
+    }
+}
+
+/// Type of two branches (such as of an `if`) which have the types `t` and `f`, if they agree.
+///
+/// A command in one branch can be run for its effects when the other produces `unit`, as in
+/// `if verbose { [echo hi] }`.
+fn branch_type(t: &Type, f: &Type) -> Option<Type> {
+    match (t, f) {
+        _ if t == f => Some(t.clone()),
+        (Type::Unit, other) | (other, Type::Unit) if other.converts_to(&Type::Unit) => {
+            Some(Type::Unit)
+        }
+        _ => None,
+    }
+}
+
+/// Output type of an infix operator, mirroring interpreter::eval_infix.
+fn infix_type(verb: &InfixVerb, t1: &Type, t2: &Type) -> Option<Type> {
+    use InfixVerb::*;
+
+    let number = |t: &Type| matches!(t, Type::Int | Type::Float);
+    match (verb, t1, t2) {
+        (Plus | Minus | Times | Divide | Modulo, Type::Int, Type::Int) => Some(Type::Int),
+        // mixing an int with a float gives a float
+        (Plus | Minus | Times | Divide | Modulo, _, _) if number(t1) && number(t2) => {
+            Some(Type::Float)
+        }
+        (Less | Greater | LessEqual | GreaterEqual | Equal | NotEqual, _, _)
+            if number(t1) && number(t2) =>
+        {
+            Some(Type::Bool)
+        }
+        (Plus, Type::Str, Type::Str) => Some(Type::Str),
+        (Plus, Type::List(_), Type::List(_)) if t1 == t2 => Some(t1.clone()),
+        (Less | Greater | LessEqual | GreaterEqual, Type::Int, Type::Int)
+        | (Less | Greater | LessEqual | GreaterEqual, Type::Str, Type::Str)
+        | (And | Or, Type::Bool, Type::Bool) => Some(Type::Bool),
+        // functions can't be compared, as there's no way to tell if two do the same thing
+        (Equal | NotEqual, Type::Fn(..), _) => None,
+        (Equal | NotEqual, _, _) if t1 == t2 => Some(Type::Bool),
+        (Pipe, Type::Command, Type::Command) => Some(Type::Command),
+        (Range, Type::Int, Type::Int) => Some(Type::List(Box::new(Type::Int))),
+        _ => None,
+    }
+}
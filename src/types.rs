@@ -1,21 +1,135 @@
-use std::collections::HashMap;
+use std::{cell::OnceCell, collections::HashMap, fmt, rc::Rc};
 
 use pest::iterators::{Pair, Pairs};
 
 use crate::parse::Rule;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Str,
     Int,
     Bool,
     Unit,
     Float,
+    Command,
+    /// Result of running a command, see `Type::output_record`.
+    Output,
+    /// A command which has been started but not waited for.
+    Process,
     List(Box<Type>),
     Map(Box<Type>, Box<Type>),
     Tuple(Vec<Type>),
     Variant(HashMap<String, Type>),
     Record(HashMap<String, Type>),
+    /// A function value (a closure or a function used by name) with its argument and output
+    /// types.
+    Fn(Vec<Type>, Box<Type>),
+    /// A type declared with `type` or `enum`, as written before it's resolved by a TypeEnv.
+    Named(String),
+    /// A recursive type referring to itself, see TypeEnv.
+    Alias(Alias),
+}
+
+/// Where a recursive type refers to itself, as in `type Tree = {children: list<Tree>};`.
+///
+/// Every reference shares the definition, which is filled in once it has been resolved.
+#[derive(Clone)]
+pub struct Alias {
+    pub name: String,
+    def: Rc<OnceCell<Type>>,
+}
+
+impl Alias {
+    pub fn def(&self) -> &Type {
+        self.def.get().expect("aliases are only used once resolved")
+    }
+}
+
+/// Aliases are equal if they're for the same declaration, rather than just the same name.
+impl PartialEq for Alias {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.def, &other.def)
+    }
+}
+
+// only the name, as the definition contains the alias itself
+impl fmt::Debug for Alias {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Alias({})", self.name)
+    }
+}
+
+/// Types declared with `type` or `enum` which are in scope.
+///
+/// Names are resolved by replacing them with their definitions, so a declared type is the same
+/// as writing it out in full. The exception is a recursive type, which would go on forever, so
+/// it refers to itself with an Alias.
+#[derive(Debug, Clone, Default)]
+pub struct TypeEnv {
+    types: HashMap<String, (Type, Alias)>,
+}
+
+impl TypeEnv {
+    /// Declare a type as written, shadowing any type with the same name. Types declared
+    /// together (such as in the same block) can refer to each other, so they're only resolved
+    /// once they've all been declared.
+    pub fn declare(&mut self, name: &str, ty: &Type) {
+        let alias = Alias {
+            name: name.to_string(),
+            def: Rc::default(),
+        };
+        self.types.insert(name.to_string(), (ty.clone(), alias));
+    }
+
+    /// A type with the names in it replaced by their definitions, or an error if a name isn't
+    /// declared.
+    pub fn resolve(&self, ty: &Type) -> Result<Type, String> {
+        self.expand(ty, &mut Vec::new())
+    }
+
+    /// `ty` with every name replaced, except those in `stack` (the types being expanded), which
+    /// are recursive.
+    fn expand(&self, ty: &Type, stack: &mut Vec<String>) -> Result<Type, String> {
+        let mut expand = |t: &Type| self.expand(t, stack);
+        let out = match ty {
+            Type::Named(name) => {
+                let Some((def, alias)) = self.types.get(name) else {
+                    return Err(format!("unknown type `{name}`"));
+                };
+                if stack.contains(name) {
+                    return Ok(Type::Alias(alias.clone()));
+                }
+                stack.push(name.clone());
+                let out = self.expand(def, stack);
+                stack.pop();
+                let out = out?;
+                if let Type::Alias(_) = out {
+                    return Err(format!("type `{name}` is defined as itself"));
+                }
+                alias.def.get_or_init(|| out.clone());
+                out
+            }
+            Type::List(t) => Type::List(Box::new(expand(t)?)),
+            Type::Map(k, v) => Type::Map(Box::new(expand(k)?), Box::new(expand(v)?)),
+            Type::Tuple(ts) => Type::Tuple(ts.iter().map(expand).collect::<Result<_, _>>()?),
+            Type::Fn(args, out) => Type::Fn(
+                args.iter().map(&mut expand).collect::<Result<_, _>>()?,
+                Box::new(expand(out)?),
+            ),
+            Type::Variant(fields) | Type::Record(fields) => {
+                let mut out = HashMap::new();
+                for (name, t) in fields {
+                    out.insert(name.clone(), expand(t)?);
+                }
+                match ty {
+                    Type::Variant(_) => Type::Variant(out),
+                    _ => Type::Record(out),
+                }
+            }
+            t => t.clone(),
+        };
+        Ok(out)
+    }
 }
 
 fn next_string(pairs: &mut Pairs<Rule>) -> String {
@@ -29,6 +143,7 @@ fn next_type(pairs: &mut Pairs<Rule>) -> Type {
 impl Type {
     pub fn parse(pair: Pair<Rule>) -> Self {
         let rule = pair.as_rule();
+        let text = pair.as_str();
         let mut inner = pair.into_inner();
         match rule {
             Rule::t_str => Self::Str,
@@ -36,6 +151,9 @@ impl Type {
             Rule::t_bool => Self::Bool,
             Rule::t_unit => Self::Unit,
             Rule::t_float => Self::Float,
+            Rule::t_command => Self::Command,
+            Rule::t_output => Self::Output,
+            Rule::t_process => Self::Process,
             Rule::t_list => Self::List(Box::new(next_type(&mut inner))),
             Rule::t_map => Self::Map(
                 Box::new(next_type(&mut inner)),
@@ -48,7 +166,7 @@ impl Type {
                     let field_name = next_string(&mut inner);
                     let field_type = next_type(&mut inner);
                     if out.contains_key(&field_name) {
-                        todo!("what to do with duplicate fields?");
+                        unreachable!("checked by parse::check_fields");
                     }
                     out.insert(field_name, field_type);
                 }
@@ -58,16 +176,197 @@ impl Type {
                     _ => unreachable!(),
                 }
             }
-            Rule::t_ident => todo!(),
+            Rule::t_fn => {
+                let mut args: Vec<_> = inner.map(Self::parse).collect();
+                let out = args.pop().expect("grammar requires an output type");
+                Self::Fn(args, Box::new(out))
+            }
+            Rule::t_ident => Self::Named(text.to_string()),
             // Rule::r#type => todo!(),
             _ => unimplemented!(),
         }
     }
 }
 
-// fn record_is_subtype() -> bool {
-// false
-// }
+impl Type {
+    /// Fields of an `output`, which can be converted to any record with a subset of them, such as
+    /// `let out: {stdout: str, status: int} = [ls];`.
+    ///
+    /// `status` is the exit status of the last stage of a pipeline and `pipestatus` has the exit
+    /// status of every stage. `stdout_bytes` and `stderr_bytes` are the raw output, whereas any
+    /// invalid UTF-8 is replaced in `stdout` and `stderr`.
+    pub fn output_record() -> Type {
+        let bytes = || Type::List(Box::new(Type::Int));
+        Type::Record(HashMap::from_iter([
+            (String::from("status"), Type::Int),
+            (String::from("success"), Type::Bool),
+            (String::from("stdout"), Type::Str),
+            (String::from("stderr"), Type::Str),
+            (String::from("pipestatus"), Type::List(Box::new(Type::Int))),
+            (String::from("stdout_bytes"), bytes()),
+            (String::from("stderr_bytes"), bytes()),
+        ]))
+    }
+
+    /// The error bound by `catch`. `kind` names the error (such as `command_failed`) and
+    /// `message` describes it. `status` is the exit status of a failed command, 127 if it
+    /// couldn't be started or 1 for any other error. `stderr` is only set for failed commands
+    /// whose stderr was captured, such as an `output` checked with `?`.
+    pub fn error_record() -> Type {
+        Type::Record(HashMap::from_iter([
+            (String::from("kind"), Type::Str),
+            (String::from("message"), Type::Str),
+            (String::from("status"), Type::Int),
+            (String::from("stderr"), Type::Str),
+        ]))
+    }
+
+    /// The definition of a recursive type, or the type itself for any other type.
+    pub fn unfold(&self) -> &Type {
+        match self {
+            Type::Alias(alias) => alias.def().unfold(),
+            t => t,
+        }
+    }
+
+    /// Whether a value of this type can (possibly) be converted to `target` by `Value::convert`.
+    ///
+    /// Conversions which depend on the value, such as parsing an `int` from a `str`, are allowed
+    /// here and can still fail at runtime.
+    pub fn converts_to(&self, target: &Type) -> bool {
+        match (self, target) {
+            // two different recursive types are never compatible, as comparing them would
+            // never end
+            (Type::Alias(a1), Type::Alias(a2)) => a1 == a2,
+            (Type::Alias(alias), target) => alias.def().converts_to(target),
+            (ty, Type::Alias(alias)) => ty.converts_to(alias.def()),
+            (Type::Str, Type::Int | Type::Float) => true,
+            // the only implicit conversion between numbers, as it can't lose anything (other
+            // than precision for very large ints)
+            (Type::Int, Type::Float) => true,
+            (Type::Str, Type::List(t)) => Type::Str.converts_to(t),
+            // a line is split into whitespace separated fields, with the rest of the line in the
+            // last field (as with bash's `read`)
+            (Type::Str, Type::Tuple(ts)) => ts.iter().all(|t| Type::Str.converts_to(t)),
+            (Type::Tuple(t1), Type::Tuple(t2)) => {
+                t1.len() == t2.len() && t1.iter().zip(t2).all(|(t1, t2)| t1.converts_to(t2))
+            }
+            (Type::Tuple(ts), Type::Str) => ts.iter().all(|t| t.converts_to(&Type::Str)),
+            (Type::List(t1), Type::List(t2)) => t1.converts_to(t2),
+            (Type::List(t), Type::Str) => t.converts_to(&Type::Str),
+            (Type::Int | Type::Float | Type::Bool, Type::Str) => true,
+            // commands are converted by running them (or waiting for a process to finish) and
+            // using their output, with stdout used when converting to a `str` and its first
+            // field when converting to a number
+            (Type::Command, Type::Process) => true,
+            // a command converted to `unit` is run for its effects, with its output not captured
+            (Type::Command, Type::Unit) => true,
+            (Type::Command | Type::Process, Type::Output) => true,
+            (Type::Command | Type::Process | Type::Output, Type::Str | Type::Int | Type::Float) => {
+                true
+            }
+            (Type::Command | Type::Process | Type::Output, Type::List(_) | Type::Tuple(_)) => {
+                Type::Str.converts_to(target)
+            }
+            (Type::Command | Type::Process | Type::Output, Type::Record(_)) => {
+                Type::output_record().converts_to(target)
+            }
+            (Type::Map(k1, v1), Type::Map(k2, v2)) => k1.converts_to(k2) && v1.converts_to(v2),
+            // a map with `str` keys converts to a record if it has every field (checked at
+            // runtime), and a record converts to a map with its field names as keys
+            (Type::Map(k, v), Type::Record(r)) => {
+                **k == Type::Str && r.values().all(|t| v.converts_to(t))
+            }
+            (Type::Record(r), Type::Map(k, v)) => {
+                Type::Str.converts_to(k) && r.values().all(|t| t.converts_to(v))
+            }
+            // a variant can be widened to a type with more tags, which is how `[ok: 5]` (of type
+            // `[ok: int]`) becomes a `[ok: int, err: str]`
+            (Type::Variant(v1), Type::Variant(v2)) => v1
+                .iter()
+                .all(|(tag, t1)| v2.get(tag).is_some_and(|t2| t1.converts_to(t2))),
+            (Type::Record(r1), Type::Record(r2)) => record_is_subtype(r1, r2),
+            // everything can be converted to itself
+            (t1, t2) => t1 == t2,
+        }
+    }
+}
+
+fn fmt_fields(f: &mut fmt::Formatter<'_>, fields: &HashMap<String, Type>) -> fmt::Result {
+    let mut fields: Vec<_> = fields.iter().collect();
+    fields.sort_by_key(|(k, _)| *k);
+    for (i, (name, ty)) in fields.into_iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{name}: {ty}")?;
+    }
+    Ok(())
+}
+
+/// Types are displayed using the same syntax they are written with.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Str => write!(f, "str"),
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::Unit => write!(f, "unit"),
+            Type::Float => write!(f, "float"),
+            Type::Command => write!(f, "command"),
+            Type::Output => write!(f, "output"),
+            Type::Process => write!(f, "process"),
+            Type::List(t) => write!(f, "list<{t}>"),
+            Type::Map(k, v) => write!(f, "map<{k}, {v}>"),
+            Type::Tuple(ts) => {
+                write!(f, "(")?;
+                for (i, t) in ts.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{t}")?;
+                }
+                if ts.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Type::Variant(fields) => {
+                write!(f, "[")?;
+                fmt_fields(f, fields)?;
+                write!(f, "]")
+            }
+            Type::Record(fields) => {
+                write!(f, "{{")?;
+                fmt_fields(f, fields)?;
+                write!(f, "}}")
+            }
+            Type::Fn(args, out) => {
+                write!(f, "fn(")?;
+                for (i, t) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{t}")?;
+                }
+                write!(f, ") -> {out}")
+            }
+            Type::Named(name) => write!(f, "{name}"),
+            Type::Alias(alias) => write!(f, "{}", alias.name),
+        }
+    }
+}
+
+/// Whether a record with the fields `r1` can be used as a record with the fields `r2`.
+///
+/// Records are structurally typed with width subtyping: a record with extra fields can be used
+/// in place of one with fewer, such as passing a `{host: str, port: int}` where a `{host: str}`
+/// is expected. Converting it drops the extra fields, and the remaining fields are converted to
+/// their types in `r2`.
+pub fn record_is_subtype(r1: &HashMap<String, Type>, r2: &HashMap<String, Type>) -> bool {
+    r2.iter()
+        .all(|(name, t2)| r1.get(name).is_some_and(|t1| t1.converts_to(t2)))
+}
 
 // // extremely temporary terrible prototype
 // impl Value {
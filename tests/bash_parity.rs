@@ -0,0 +1,282 @@
+//! Scripts which should behave the same when interpreted and when transpiled to bash: the same
+//! stdout, and both either succeeding or failing.
+
+use std::{path::PathBuf, process::Command};
+
+/// Stdout of a run, and whether it succeeded.
+fn outcome(out: std::process::Output) -> (String, bool) {
+    (
+        String::from_utf8_lossy(&out.stdout).into_owned(),
+        out.status.success(),
+    )
+}
+
+fn assert_parity(name: &str, source: &str) {
+    let path: PathBuf = std::env::temp_dir().join(format!("kley-{}-{name}.ky", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    let kley = env!("CARGO_BIN_EXE_kley");
+
+    let interpreted = Command::new(kley).arg(&path).output().unwrap();
+    let build = Command::new(kley).arg("build").arg(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(
+        build.status.success(),
+        "{name} doesn't transpile: {}",
+        String::from_utf8_lossy(&build.stderr)
+    );
+    let script = String::from_utf8(build.stdout).unwrap();
+    let transpiled = Command::new("bash")
+        .arg("-c")
+        .arg(&script)
+        .output()
+        .unwrap();
+
+    assert_eq!(
+        outcome(interpreted),
+        outcome(transpiled),
+        "{name} behaves differently in bash:\n{script}"
+    );
+}
+
+#[test]
+fn functions_and_loops() {
+    assert_parity(
+        "functions",
+        r#"
+        fn fib(n: int) -> int {
+            if n < 2 { return n; }
+            fib(n - 1) + fib(n - 2)
+        }
+        for i: int in 0..8 {
+            if i == 6 { break; }
+            [echo "fib({i}) = {fib(i)}"];
+        }
+        "#,
+    );
+}
+
+#[test]
+fn variables_named_like_bash_ones() {
+    assert_parity(
+        "env-names",
+        r#"
+        let PATH: str = "x";
+        let IFS: str = "q";
+        let HOME: str = "/nowhere";
+        let UID: int = 3;
+        [ls / > /dev/null];
+        [echo {PATH} {IFS} {HOME} {UID}];
+        "#,
+    );
+}
+
+#[test]
+fn failing_substitution_in_function() {
+    assert_parity(
+        "local",
+        r#"
+        fn f() -> str { let s: str = [sh -c "exit 3"]; s }
+        [echo before];
+        let x: str = f();
+        [echo after];
+        "#,
+    );
+}
+
+#[test]
+fn failing_command_stored_as_list() {
+    assert_parity(
+        "list",
+        r#"
+        let l: list<str> = [sh -c "echo a b; exit 3"];
+        [echo after];
+        "#,
+    );
+}
+
+#[test]
+fn errexit_is_scoped_to_its_block() {
+    assert_parity(
+        "errexit-block",
+        r#"
+        if true { errexit(false); [false]; [echo inside]; }
+        [echo between];
+        [false];
+        [echo after];
+        "#,
+    );
+}
+
+#[test]
+fn errexit_is_restored_when_leaving_early() {
+    assert_parity(
+        "errexit-break",
+        r#"
+        fn g(n: int) -> int {
+            if n > 0 {
+                errexit(false);
+                [false];
+                return n;
+            }
+            0
+        }
+        for i: int in 0..3 {
+            errexit(false);
+            [false];
+            if i == 1 { break; }
+        }
+        [echo {g(1)}];
+        [false];
+        [echo after];
+        "#,
+    );
+}
+
+#[test]
+fn conversions_to_int() {
+    assert_parity(
+        "to-int",
+        r#"
+        let s: str = "  042 ";
+        let n: int = s;
+        let m: int = [echo "  -17 rest"];
+        fn times(x: int, y: int = 2) -> int { x * y }
+        let xs: list<int> = "1 010 -3";
+        [echo {n + 1} {m} {times(n)} {xs[1] + 1}];
+        for v: int in [printf "4\n05\n"] { [echo {v + 1}]; }
+        let lo: int = "-9223372036854775808";
+        [echo {lo}];
+        "#,
+    );
+}
+
+#[test]
+fn str_which_is_not_an_int() {
+    assert_parity(
+        "not-int",
+        r#"
+        let s: str = "abc";
+        let n: int = s;
+        [echo reached {n}];
+        "#,
+    );
+}
+
+#[test]
+fn str_with_code_is_not_run_as_an_int() {
+    assert_parity(
+        "int-injection",
+        r#"
+        let s: str = "a[$(echo pwned)]";
+        let n: int = s;
+        [echo {n + 1}];
+        "#,
+    );
+}
+
+#[test]
+fn str_too_large_for_an_int() {
+    assert_parity(
+        "int-range",
+        r#"
+        let n: int = "9223372036854775808";
+        [echo reached {n}];
+        "#,
+    );
+}
+
+#[test]
+fn arithmetic() {
+    assert_parity(
+        "arithmetic",
+        r#"
+        let x: int = 5;
+        let y: int = -(-x);
+        [echo {y} {-x} {- -3} {x - -x} {7 / -2} {-7 % 3}];
+        let m: int = 9223372036854775807;
+        [echo {m - 1 + 1} {-m - 1}];
+        "#,
+    );
+}
+
+#[test]
+fn division_by_zero() {
+    assert_parity(
+        "div-zero",
+        r#"
+        let z: int = 0;
+        [echo before];
+        [echo {1 / z}];
+        [echo after];
+        "#,
+    );
+}
+
+#[test]
+fn integer_overflow() {
+    assert_parity(
+        "overflow",
+        r#"
+        let m: int = 9223372036854775807;
+        [echo {m + 1}];
+        [echo after];
+        "#,
+    );
+    assert_parity(
+        "overflow-times",
+        r#"
+        let m: int = 4294967296;
+        [echo {m * m}];
+        "#,
+    );
+    assert_parity(
+        "overflow-negate",
+        r#"
+        let m: int = -9223372036854775807 - 1;
+        [echo {-m}];
+        "#,
+    );
+}
+
+#[test]
+fn index_out_of_bounds() {
+    assert_parity(
+        "index",
+        r#"
+        let xs: list<str> = "a b c";
+        [echo {xs[-1]} {xs[0]}];
+        [echo {xs[7]}];
+        [echo after];
+        "#,
+    );
+    assert_parity(
+        "slice",
+        r#"
+        let xs: list<str> = "a b";
+        let ys: list<str> = xs[1..5];
+        [echo after];
+        "#,
+    );
+}
+
+#[test]
+fn assigning_to_a_range_loop_variable() {
+    assert_parity(
+        "range-var",
+        r#"
+        for i: int in 0..3 { [echo {i}]; i = 10; [echo {i}]; }
+        "#,
+    );
+}
+
+#[test]
+fn command_output_is_trimmed() {
+    assert_parity(
+        "trim",
+        r#"
+        let s: str = [printf "  a  b  "];
+        let t: str = [printf "\n\t x \n\n"];
+        [echo "<{s}>" "<{t}>"];
+        "#,
+    );
+}